@@ -0,0 +1,3 @@
+drop index if exists "role_name_gist_idx";
+drop index if exists "role_owner_id_name_key";
+alter table "role" alter column "owner_id" drop not null;
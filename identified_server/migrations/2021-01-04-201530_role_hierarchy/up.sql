@@ -0,0 +1,3 @@
+alter table "role" alter column "owner_id" set not null;
+create unique index "role_owner_id_name_key" on "role" ("owner_id", "name");
+create index "role_name_gist_idx" on "role" using gist ("name");
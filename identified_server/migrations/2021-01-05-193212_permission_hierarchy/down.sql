@@ -0,0 +1,2 @@
+drop index if exists "permission_name_gist_idx";
+drop index if exists "permission_owner_id_name_key";
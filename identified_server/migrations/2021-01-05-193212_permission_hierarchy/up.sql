@@ -0,0 +1,2 @@
+create unique index "permission_owner_id_name_key" on "permission" ("owner_id", "name");
+create index "permission_name_gist_idx" on "permission" using gist ("name");
@@ -0,0 +1,3 @@
+drop index if exists "role_permission_role_id_permission_id_key";
+drop index if exists "user_permission_user_id_permission_id_key";
+drop index if exists "user_role_user_id_role_id_key";
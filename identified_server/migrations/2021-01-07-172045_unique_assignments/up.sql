@@ -0,0 +1,3 @@
+create unique index "user_role_user_id_role_id_key" on "user_role" ("user_id", "role_id");
+create unique index "user_permission_user_id_permission_id_key" on "user_permission" ("user_id", "permission_id");
+create unique index "role_permission_role_id_permission_id_key" on "role_permission" ("role_id", "permission_id");
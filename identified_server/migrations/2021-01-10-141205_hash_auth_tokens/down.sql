@@ -0,0 +1,2 @@
+-- Hashed tokens cannot be turned back into raw tokens.
+update "internal_user" set "auth_token" = null, "expires_on" = null;
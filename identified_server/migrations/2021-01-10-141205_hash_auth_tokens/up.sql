@@ -0,0 +1,3 @@
+-- Tokens are stored as HMAC-SHA256 hashes from now on, existing raw tokens
+-- can no longer be matched.
+update "internal_user" set "auth_token" = null, "expires_on" = null;
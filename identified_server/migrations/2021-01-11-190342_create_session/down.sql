@@ -0,0 +1,3 @@
+alter table "internal_user" add column "auth_token" text null;
+alter table "internal_user" add column "expires_on" timestamptz;
+drop table if exists "session";
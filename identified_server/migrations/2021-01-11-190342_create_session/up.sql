@@ -0,0 +1,18 @@
+create table "session" (
+  "id" bigserial primary key,
+  "internal_user_id" bigint not null,
+  "token" text not null,
+  "created_on" timestamptz not null,
+  "last_seen" timestamptz not null,
+  "expires_on" timestamptz not null,
+  "user_agent" text,
+  "ip_address" text
+);
+
+alter table "session" add constraint "session_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+create unique index "session_token_key" on "session" ("token");
+create index "session_internal_user_id_idx" on "session" ("internal_user_id");
+
+-- Every login gets its own row in "session" now.
+alter table "internal_user" drop column "auth_token";
+alter table "internal_user" drop column "expires_on";
@@ -0,0 +1,2 @@
+alter table "internal_user" drop column "password_iterations";
+alter table "internal_user" drop column "password_algorithm";
@@ -0,0 +1,6 @@
+-- Passwords set so far were hashed with PBKDF2 using the 1000 iterations
+-- main.rs configured.
+alter table "internal_user" add column "password_algorithm" text not null default 'pbkdf2_sha512';
+alter table "internal_user" add column "password_iterations" integer not null default 1000;
+alter table "internal_user" alter column "password_algorithm" drop default;
+alter table "internal_user" alter column "password_iterations" drop default;
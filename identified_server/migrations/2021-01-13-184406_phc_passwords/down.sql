@@ -0,0 +1,24 @@
+-- Only PBKDF2 hashes can be turned back into the old columns, anyone with an
+-- Argon2 hash is left with an empty password and has to get it reset.
+create function pg_temp.unpadded_base64(value text) returns bytea as $$
+  select decode(rpad(value, ((length(value) + 3) / 4) * 4, '='), 'base64')
+$$ language sql;
+
+alter table "internal_user" add column "salt" text not null default '';
+alter table "internal_user" add column "password_algorithm" text not null default 'pbkdf2_sha512';
+alter table "internal_user" add column "password_iterations" integer not null default 1000;
+
+update "internal_user" set
+  "salt" = convert_from(pg_temp.unpadded_base64(split_part("password", '$', 4)), 'UTF8'),
+  "password_iterations" = substring(split_part("password", '$', 3) from 3)::integer
+where "password" like '$pbkdf2-sha512$%';
+
+alter table "internal_user" alter column "password" type bytea using
+  case
+    when "password" like '$pbkdf2-sha512$%' then pg_temp.unpadded_base64(split_part("password", '$', 5))
+    else ''::bytea
+  end;
+
+alter table "internal_user" alter column "salt" drop default;
+alter table "internal_user" alter column "password_algorithm" drop default;
+alter table "internal_user" alter column "password_iterations" drop default;
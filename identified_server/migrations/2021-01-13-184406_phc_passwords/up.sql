@@ -0,0 +1,10 @@
+-- Re-encode the existing PBKDF2 hashes as PHC strings,
+-- `$pbkdf2-sha512$i=<iterations>$<salt>$<hash>`, using unpadded base64.
+alter table "internal_user" alter column "password" type text using
+  '$pbkdf2-sha512$i=' || "password_iterations"
+  || '$' || rtrim(replace(encode(convert_to("salt", 'UTF8'), 'base64'), E'\n', ''), '=')
+  || '$' || rtrim(replace(encode("password", 'base64'), E'\n', ''), '=');
+
+alter table "internal_user" drop column "salt";
+alter table "internal_user" drop column "password_algorithm";
+alter table "internal_user" drop column "password_iterations";
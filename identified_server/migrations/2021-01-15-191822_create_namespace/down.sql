@@ -0,0 +1,11 @@
+drop index "role_namespace_id_name_key";
+drop index "permission_namespace_id_name_key";
+create unique index "role_owner_id_name_key" on "role" ("owner_id", "name");
+create unique index "permission_owner_id_name_key" on "permission" ("owner_id", "name");
+
+alter table "user" drop column "namespace_id";
+alter table "role" drop column "namespace_id";
+alter table "permission" drop column "namespace_id";
+
+drop table "namespace_member";
+drop table "namespace";
@@ -0,0 +1,46 @@
+create table "namespace" (
+  "id" bigserial primary key,
+  "name" text not null,
+  "owner_id" bigint not null
+);
+
+create table "namespace_member" (
+  "id" bigserial primary key,
+  "namespace_id" bigint not null,
+  "internal_user_id" bigint not null
+);
+
+alter table "namespace" add constraint "namespace_fk_owner_id" foreign key ("owner_id") references "internal_user" ("id");
+alter table "namespace_member" add constraint "namespace_member_fk_namespace_id" foreign key ("namespace_id") references "namespace" ("id") on delete cascade;
+alter table "namespace_member" add constraint "namespace_member_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+create unique index "namespace_name_key" on "namespace" ("name");
+create unique index "namespace_member_namespace_id_internal_user_id_key" on "namespace_member" ("namespace_id", "internal_user_id");
+create index "namespace_member_internal_user_id_idx" on "namespace_member" ("internal_user_id");
+
+-- Rows used to be scoped to the internal user owning them. Every internal user
+-- gets a namespace of their own, named after their email, holding those rows.
+insert into "namespace" ("name", "owner_id")
+  select "email", "id" from "internal_user" order by "id";
+insert into "namespace_member" ("namespace_id", "internal_user_id")
+  select "id", "owner_id" from "namespace";
+
+alter table "user" add column "namespace_id" bigint;
+alter table "role" add column "namespace_id" bigint;
+alter table "permission" add column "namespace_id" bigint;
+update "user" set "namespace_id" = "namespace"."id" from "namespace" where "namespace"."owner_id" = "user"."owner_id";
+update "role" set "namespace_id" = "namespace"."id" from "namespace" where "namespace"."owner_id" = "role"."owner_id";
+update "permission" set "namespace_id" = "namespace"."id" from "namespace" where "namespace"."owner_id" = "permission"."owner_id";
+alter table "user" alter column "namespace_id" set not null;
+alter table "role" alter column "namespace_id" set not null;
+alter table "permission" alter column "namespace_id" set not null;
+
+alter table "user" add constraint "user_fk_namespace_id" foreign key ("namespace_id") references "namespace" ("id");
+alter table "role" add constraint "role_fk_namespace_id" foreign key ("namespace_id") references "namespace" ("id");
+alter table "permission" add constraint "permission_fk_namespace_id" foreign key ("namespace_id") references "namespace" ("id");
+create index "user_namespace_id_idx" on "user" ("namespace_id");
+
+-- Names are unique within a namespace, whoever created them.
+drop index "role_owner_id_name_key";
+drop index "permission_owner_id_name_key";
+create unique index "role_namespace_id_name_key" on "role" ("namespace_id", "name");
+create unique index "permission_namespace_id_name_key" on "permission" ("namespace_id", "name");
@@ -0,0 +1,3 @@
+alter table "internal_user" add column "admin" boolean not null default false;
+update "internal_user" set "admin" = 'internal:manage' = any("scopes");
+alter table "internal_user" drop column "scopes";
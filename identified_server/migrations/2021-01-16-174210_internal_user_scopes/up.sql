@@ -0,0 +1,9 @@
+alter table "internal_user" add column "scopes" text[] not null default '{}';
+-- Admins keep every right they had, everyone else keeps working within their
+-- namespaces.
+update "internal_user" set "scopes" = case when "admin" then
+    array['internal:manage', 'namespaces:manage', 'permissions:check', 'permissions:read', 'permissions:write', 'roles:read', 'roles:write', 'users:read', 'users:write']
+else
+    array['permissions:check', 'permissions:read', 'permissions:write', 'roles:read', 'roles:write', 'users:read', 'users:write']
+end;
+alter table "internal_user" drop column "admin";
@@ -0,0 +1,18 @@
+create table "api_key" (
+  "id" bigserial primary key,
+  "internal_user_id" bigint not null,
+  "name" text not null,
+  -- The start of the key, to tell keys apart without storing them
+  "prefix" text not null,
+  "key" text not null,
+  "scopes" text[] not null,
+  -- Empty allows every namespace the internal user is a member of
+  "namespace_ids" bigint[] not null default '{}',
+  "created_on" timestamptz not null,
+  "expires_on" timestamptz,
+  "last_used" timestamptz
+);
+
+alter table "api_key" add constraint "api_key_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+create unique index "api_key_key_key" on "api_key" ("key");
+create index "api_key_internal_user_id_idx" on "api_key" ("internal_user_id");
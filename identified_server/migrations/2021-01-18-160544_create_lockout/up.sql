@@ -0,0 +1,13 @@
+-- Failed logins, per account (by email, whether it exists or not) and per IP
+-- address. Rows are cleared on a successful login to the account, or by an
+-- admin.
+create table "lockout" (
+  "id" bigserial primary key,
+  "kind" text not null,
+  "subject" text not null,
+  "failures" integer not null,
+  "last_failure" timestamptz not null,
+  "locked_until" timestamptz
+);
+
+create unique index "lockout_kind_subject_key" on "lockout" ("kind", "subject");
@@ -0,0 +1,3 @@
+drop table "recovery_code";
+drop table "totp";
+alter table "internal_user" drop column "totp_required";
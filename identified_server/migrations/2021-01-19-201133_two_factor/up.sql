@@ -0,0 +1,25 @@
+-- Set by admins, an internal user that is required to use two-factor
+-- authentication cannot do anything but enroll until they have.
+alter table "internal_user" add column "totp_required" boolean not null default false;
+
+-- The secret is kept in the clear, codes cannot be checked without it.
+create table "totp" (
+  "internal_user_id" bigint primary key,
+  "secret" text not null,
+  -- Unset until the first code was entered, two-factor authentication is
+  -- only enforced from then on
+  "confirmed_on" timestamptz,
+  -- The last step a code was accepted for, older codes are refused
+  "last_step" bigint not null default 0
+);
+
+alter table "totp" add constraint "totp_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+
+create table "recovery_code" (
+  "id" bigserial primary key,
+  "internal_user_id" bigint not null,
+  "code" text not null
+);
+
+alter table "recovery_code" add constraint "recovery_code_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+create index "recovery_code_internal_user_id_idx" on "recovery_code" ("internal_user_id");
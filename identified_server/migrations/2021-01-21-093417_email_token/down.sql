@@ -0,0 +1,2 @@
+drop table "email_token";
+alter table "internal_user" drop column "email_verified_on";
@@ -0,0 +1,21 @@
+-- Unset until the internal user proves they can read mail sent to the email,
+-- and again whenever the email changes.
+alter table "internal_user" add column "email_verified_on" timestamptz;
+
+-- Password reset and email verification tokens. Only the keyed hash of a
+-- token is stored, the row is deleted once it is used.
+create table "email_token" (
+  "id" bigserial primary key,
+  "internal_user_id" bigint not null,
+  -- `password_reset` or `email_verification`
+  "purpose" text not null,
+  -- The email the token was sent to, it is void once the email changes
+  "email" text not null,
+  "token" text not null,
+  "created_on" timestamptz not null,
+  "expires_on" timestamptz not null
+);
+
+alter table "email_token" add constraint "email_token_fk_internal_user_id" foreign key ("internal_user_id") references "internal_user" ("id") on delete cascade;
+create unique index "email_token_token_key" on "email_token" ("token");
+create index "email_token_internal_user_id_idx" on "email_token" ("internal_user_id");
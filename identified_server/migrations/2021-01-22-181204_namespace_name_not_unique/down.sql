@@ -0,0 +1,2 @@
+drop index "namespace_name_idx";
+create unique index "namespace_name_key" on "namespace" ("name");
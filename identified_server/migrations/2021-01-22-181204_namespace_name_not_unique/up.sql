@@ -0,0 +1,6 @@
+-- Namespaces are addressed by id, the name is only a label. Internal users get
+-- a namespace named after their email, which a unique name would refuse when
+-- another namespace already took that name, e.g. the one left behind by an
+-- earlier internal user with the same email.
+drop index "namespace_name_key";
+create index "namespace_name_idx" on "namespace" ("name");
@@ -1,3 +1,15 @@
+pub mod api_key;
+pub mod email;
 pub mod helpers;
 pub mod internal;
+pub mod lockout;
+pub mod me;
+pub mod namespace;
+pub mod password;
+pub mod permission;
+pub mod role;
 pub mod root;
+pub mod session;
+pub mod setup;
+pub mod totp;
+pub mod user;
@@ -0,0 +1,177 @@
+use chrono::Utc;
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    database::models::api_key::{ApiKey, CreatedApiKey, PublicApiKey, SubmitApiKey},
+    database::models::internal_user::InternalUser,
+    database::models::namespace::Namespace,
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter(db_config.clone(), session.clone())
+                .or(create_filter(db_config.clone(), session.clone()))
+                .or(revoke_filter(db_config, session)),
+        )
+    }
+
+    pub fn all_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::all)
+    }
+
+    pub fn create_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::create)
+    }
+
+    pub fn revoke_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::revoke)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    pub async fn all(iuser: InternalUser, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results: Vec<PublicApiKey> = ApiKey::all(iuser.id, &connection)
+            .map_err(query_rejection)?
+            .into_iter()
+            .map(PublicApiKey::from)
+            .collect();
+        Ok(warp::reply::json(&results))
+    }
+
+    // A key is limited to scopes and namespaces the caller has themselves.
+    pub async fn create(
+        submitted: SubmitApiKey,
+        iuser: InternalUser,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let invalid = |field, error| Err(warp::reject::custom(InputError::new(field, error)));
+        if submitted.name.trim().is_empty() {
+            return invalid("name", ValidationError::Required);
+        }
+        if submitted.scopes.is_empty() {
+            return invalid("scopes", ValidationError::Required);
+        }
+        if !submitted.scopes.iter().all(|&scope| iuser.has_scope(scope)) {
+            return invalid("scopes", ValidationError::Invalid);
+        }
+        if submitted
+            .expires_on
+            .is_some_and(|expires_on| expires_on <= Utc::now())
+        {
+            return invalid("expires_on", ValidationError::Invalid);
+        }
+        let connection = get_connection(session)?;
+        let namespaces = Namespace::of_member(iuser.id, &connection).map_err(query_rejection)?;
+        let is_member = |id: &i64| namespaces.iter().any(|namespace| namespace.id == *id);
+        if !submitted.namespace_ids.iter().all(is_member) {
+            return invalid("namespace_ids", ValidationError::Invalid);
+        }
+        let (api_key, key) =
+            ApiKey::create(iuser.id, submitted, db_config, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&CreatedApiKey {
+            api_key: PublicApiKey::from(api_key),
+            key,
+        }))
+    }
+
+    pub async fn revoke(
+        by_id: i64,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = ApiKey::revoke(iuser.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::api_key::API_KEY_PREFIX;
+
+    fn api_key() -> ApiKey {
+        ApiKey {
+            id: 1,
+            internal_user_id: 1,
+            name: String::from("billing"),
+            prefix: String::from("idk_AbCd"),
+            key: String::from("secret-key-hash"),
+            scopes: vec![String::from("permissions:check")],
+            namespace_ids: vec![2],
+            created_on: Utc::now(),
+            expires_on: None,
+            last_used: None,
+        }
+    }
+
+    #[test]
+    fn public_api_key_has_no_key() {
+        let public = PublicApiKey::from(api_key());
+        let serialized = serde_json::to_string(&public).unwrap();
+        assert!(!serialized.contains("secret-key-hash"), "{}", serialized);
+        assert!(!serialized.contains("\"key\""), "{}", serialized);
+        assert!(public.prefix.starts_with(API_KEY_PREFIX));
+    }
+
+    #[test]
+    fn api_key_restrictions() {
+        let key = api_key();
+        assert!(!key.is_expired());
+        assert!(key.allows_namespace(2));
+        assert!(!key.allows_namespace(3));
+        let unrestricted = ApiKey {
+            namespace_ids: vec![],
+            ..api_key()
+        };
+        assert!(unrestricted.allows_namespace(3));
+        let expired = ApiKey {
+            expires_on: Some(Utc::now()),
+            ..api_key()
+        };
+        assert!(expired.is_expired());
+    }
+}
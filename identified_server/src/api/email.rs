@@ -0,0 +1,136 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::public::reply_one,
+    database::models::email_token::{EmailToken, SubmitToken, EMAIL_VERIFICATION},
+    database::models::internal_user::InternalUser,
+    database::{get_connection, DatabaseConfig},
+    mailer::Outbox,
+    utils::common::*,
+    utils::errors::*,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            verify_filter(db_config.clone(), session.clone(), outbox)
+                .or(confirm_filter(db_config, session)),
+        )
+    }
+
+    pub fn verify_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("verify"))
+            .and(end())
+            .and(with_login(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and(with(outbox))
+            .and_then(handlers::verify)
+    }
+
+    // Needs no login, the token is proof enough and is usually opened from
+    // the mail on another device.
+    pub fn confirm_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("confirm"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::confirm)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Sends a token to the current email of the internal user.
+    pub async fn verify(
+        iuser: InternalUser,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let (email_token, token) =
+            EmailToken::create(&iuser, EMAIL_VERIFICATION, db_config, &connection)
+                .map_err(query_rejection)?;
+        outbox
+            .email_verification(&iuser.email, &token, email_token.expires_on)
+            .map_err(|e| warp::reject::custom(ServerError::MailError(e)))?;
+        Ok(http::StatusCode::ACCEPTED)
+    }
+
+    pub async fn confirm(
+        submitted: SubmitToken,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let iuser =
+            EmailToken::consume(&submitted.token, EMAIL_VERIFICATION, db_config, &connection)
+                .map_err(query_rejection)?
+                .ok_or_else(|| {
+                    warp::reject::custom(InputError::new("token", ValidationError::Invalid))
+                })?;
+        let result =
+            InternalUser::mark_email_verified(iuser.id, &connection).map_err(query_rejection)?;
+        Ok(reply_one(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::{test_internal_user, PublicInternalUser};
+    use crate::database::test_session;
+
+    #[tokio::test]
+    async fn confirming_verifies_the_email_once() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let token = {
+            let connection = session.connection_pool.get().unwrap();
+            let iuser = test_internal_user("confirm@example.com", &connection);
+            EmailToken::create(&iuser, EMAIL_VERIFICATION, db_config.clone(), &connection)
+                .unwrap()
+                .1
+        };
+        let filter = filters::confirm_filter(db_config, session).recover(handle_rejection);
+        let confirm = || {
+            warp::test::request()
+                .method("POST")
+                .path("/confirm")
+                .json(&SubmitToken {
+                    token: token.clone(),
+                })
+        };
+        let response = confirm().reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let confirmed: PublicInternalUser = serde_json::from_slice(response.body()).unwrap();
+        assert!(confirmed.email_verified_on.is_some());
+        assert_eq!(confirm().reply(&filter).await.status(), 422);
+    }
+}
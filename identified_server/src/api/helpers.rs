@@ -1 +1,4 @@
-pub mod authorization;
\ No newline at end of file
+pub mod authorization;
+pub mod public;
+pub mod subscription;
+pub mod tree;
@@ -1,53 +1,379 @@
-use crate::utils::common::with_predicate;
 use std::sync::Arc;
 use warp::{reject, Filter, Rejection};
 
 use crate::{
-    database::get_connection,
+    database::models::api_key::{ApiKey, API_KEY_PREFIX},
     database::models::internal_user::InternalUser,
+    database::models::namespace::Namespace,
+    database::models::session::AuthSession,
+    database::models::totp::Totp,
+    database::{get_connection, DatabaseConfig},
+    tls::ConnectionInfo,
     utils::common::{with, Session},
     utils::errors::*,
+    utils::scope::Scope,
 };
 
-pub fn admin(iuser: InternalUser) -> Result<InternalUser, Rejection> {
-    match iuser.admin {
-        true => Ok(iuser),
+// The authenticated internal user, and the API key they authenticated with
+// if any. Scopes of the internal user are already narrowed down to the key.
+fn with_caller(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser, Option<ApiKey>), Error = Rejection> + Clone {
+    warp::any()
+        .and(with(db_config))
+        .and(with(session))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::ext::optional::<ConnectionInfo>())
+        .and_then(check_authenticated)
+        .untuple_one()
+}
+
+fn with_scope(
+    scope: Scope,
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser, Option<ApiKey>), Error = Rejection> + Clone {
+    with_caller(db_config, session)
+        .and_then(
+            move |iuser: InternalUser, api_key: Option<ApiKey>| async move {
+                match iuser.has_scope(scope) {
+                    true => Ok((iuser, api_key)),
+                    false => Err(reject::custom(AuthorizationError::Unauthorized)),
+                }
+            },
+        )
+        .untuple_one()
+}
+
+// Any authenticated internal user, whatever their scopes.
+pub fn with_authentication(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser,), Error = Rejection> + Clone {
+    with_caller(db_config, session).map(|iuser, _api_key| iuser)
+}
+
+// An authenticated internal user holding `scope`. These routes reach beyond a
+// single namespace, so API keys limited to namespaces are refused.
+pub fn with_authorization(
+    scope: Scope,
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser,), Error = Rejection> + Clone {
+    with_scope(scope, db_config, session).and_then(
+        |iuser: InternalUser, api_key: Option<ApiKey>| async move {
+            check_unrestricted(api_key.as_ref()).map(|_| iuser)
+        },
+    )
+}
+
+fn check_unrestricted(api_key: Option<&ApiKey>) -> Result<(), Rejection> {
+    match api_key.is_none_or(ApiKey::allows_every_namespace) {
+        true => Ok(()),
         false => Err(reject::custom(AuthorizationError::Unauthorized)),
     }
 }
 
-pub fn default(iuser: InternalUser) -> Result<InternalUser, Rejection> {
-    Ok(iuser)
+// The namespace a request acts on is picked with this header. It may be left
+// out by internal users that are a member of a single namespace.
+pub const NAMESPACE_HEADER: &str = "x-namespace";
+
+// Authorizes the caller and resolves the namespace they act on, rejecting
+// namespaces they are not a member of or their API key does not allow.
+pub fn with_member(
+    scope: Scope,
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser, Namespace), Error = Rejection> + Clone {
+    with_scope(scope, db_config, session.clone())
+        .and(warp::header::optional::<i64>(NAMESPACE_HEADER))
+        .and(with(session))
+        .and_then(check_member)
+        .untuple_one()
 }
 
-pub fn with_authorization(
-    requires_admin: bool,
+// For routes that only need the namespace, not who is acting on it.
+pub fn with_namespace(
+    scope: Scope,
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (Namespace,), Error = Rejection> + Clone {
+    with_member(scope, db_config, session).map(|_iuser, namespace| namespace)
+}
+
+pub async fn check_member(
+    iuser: InternalUser,
+    api_key: Option<ApiKey>,
+    namespace_id: Option<i64>,
+    session: Arc<Session>,
+) -> Result<(InternalUser, Namespace), Rejection> {
+    let connection = get_connection(session)?;
+    let namespaces = Namespace::of_member(iuser.id, &connection).map_err(query_rejection)?;
+    let namespace = resolve_namespace(namespace_id, namespaces, |id| {
+        api_key.as_ref().is_none_or(|key| key.allows_namespace(id))
+    })?;
+    Ok((iuser, namespace))
+}
+
+// Picks the requested namespace among the ones the caller is a member of and
+// that are `allowed`, or the only one of them when none is requested.
+pub fn resolve_namespace(
+    requested: Option<i64>,
+    namespaces: Vec<Namespace>,
+    allowed: impl Fn(i64) -> bool,
+) -> Result<Namespace, Rejection> {
+    let mut namespaces: Vec<Namespace> = namespaces
+        .into_iter()
+        .filter(|namespace| allowed(namespace.id))
+        .collect();
+    match requested {
+        Some(id) => namespaces
+            .into_iter()
+            .find(|namespace| namespace.id == id)
+            .ok_or_else(|| reject::custom(AuthorizationError::Unauthorized)),
+        None if namespaces.len() == 1 => Ok(namespaces.remove(0)),
+        None => Err(reject::custom(InputError::new(
+            NAMESPACE_HEADER,
+            ValidationError::Required,
+        ))),
+    }
+}
+
+// Only accepts login sessions, for routes that neither API keys nor client
+// certificates should reach, like creating API keys.
+pub fn with_login(
+    db_config: Arc<DatabaseConfig>,
     session: Arc<Session>,
 ) -> impl Filter<Extract = (InternalUser,), Error = Rejection> + Clone {
-    let predicate = match requires_admin {
-        true => admin,
-        false => default,
-    };
+    with_auth_session(db_config, session).map(|iuser, _auth_session| iuser)
+}
+
+// Like `with_authorization`, but also hands over the session the token
+// belongs to, for routes that act on the current session.
+pub fn with_auth_session(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+) -> impl Filter<Extract = (InternalUser, AuthSession), Error = Rejection> + Clone {
     warp::any()
+        .and(with(db_config))
         .and(with(session))
         .and(warp::header::optional::<String>("authorization"))
-        .and(with_predicate(predicate))
         .and_then(check_authorized)
+        .untuple_one()
+}
+
+// Internal users that are required to use two-factor authentication cannot
+// use anything but the routes for sessions and enrolling until they do.
+pub async fn check_authenticated(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+    bearer_token: Option<String>,
+    connection_info: Option<ConnectionInfo>,
+) -> Result<(InternalUser, Option<ApiKey>), Rejection> {
+    let (iuser, api_key) =
+        authenticate(db_config, session.clone(), bearer_token, connection_info).await?;
+    if iuser.totp_required {
+        let connection = get_connection(session)?;
+        if !Totp::is_enabled(iuser.id, &connection).map_err(query_rejection)? {
+            return Err(reject::custom(AuthorizationError::TwoFactorRequired));
+        }
+    }
+    Ok((iuser, api_key))
+}
+
+// A bearer token takes precedence, it is either an API key or the token of a
+// session. Otherwise a client certificate mapped to an internal user is
+// accepted. API keys and certificates only count for routes that do not need
+// a session.
+async fn authenticate(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+    bearer_token: Option<String>,
+    connection_info: Option<ConnectionInfo>,
+) -> Result<(InternalUser, Option<ApiKey>), Rejection> {
+    let connection_info = match (bearer_token, connection_info) {
+        (Some(key), _) if key.starts_with(API_KEY_PREFIX) => {
+            return check_api_key(db_config, session, key).map(|(iuser, key)| (iuser, Some(key)))
+        }
+        (None, Some(info)) if info.client_subject.is_some() => info,
+        (bearer_token, _) => {
+            return check_authorized(db_config, session, bearer_token)
+                .await
+                .map(|(iuser, _)| (iuser, None))
+        }
+    };
+    match connection_info.client_user {
+        Some(email) => {
+            let connection = get_connection(session)?;
+            InternalUser::find_by_email(email, &connection)
+                .map(|iuser| (iuser, None))
+                .map_err(|_| reject::custom(AuthorizationError::UnknownCertificate))
+        }
+        None => Err(reject::custom(AuthorizationError::UnknownCertificate)),
+    }
+}
+
+fn check_api_key(
+    db_config: Arc<DatabaseConfig>,
+    session: Arc<Session>,
+    key: String,
+) -> Result<(InternalUser, ApiKey), Rejection> {
+    let connection = get_connection(session)?;
+    match ApiKey::find_by_key(key, db_config, &connection) {
+        Ok((api_key, _)) if api_key.is_expired() => {
+            Err(reject::custom(AuthorizationError::ExpiredToken))
+        }
+        Ok((api_key, mut iuser)) => {
+            ApiKey::touch(api_key.id, &connection).map_err(query_rejection)?;
+            api_key.restrict(&mut iuser);
+            Ok((iuser, api_key))
+        }
+        Err(_) => Err(reject::custom(AuthorizationError::InvalidToken)),
+    }
 }
 
 pub async fn check_authorized(
+    db_config: Arc<DatabaseConfig>,
     session: Arc<Session>,
     bearer_token: Option<String>,
-    predicate: fn(InternalUser) -> Result<InternalUser, Rejection>,
-) -> Result<InternalUser, Rejection> {
+) -> Result<(InternalUser, AuthSession), Rejection> {
     match bearer_token {
+        Some(token) if token.starts_with(API_KEY_PREFIX) => {
+            Err(reject::custom(AuthorizationError::Unauthorized))
+        }
         Some(token) => {
             let connection = get_connection(session)?;
-            match InternalUser::find_by_auth_token(token, &connection) {
-                Ok(iuser) => predicate(iuser),
+            match AuthSession::find_by_token(token, db_config, &connection) {
+                Ok((auth_session, _)) if auth_session.is_expired() => {
+                    Err(reject::custom(AuthorizationError::ExpiredToken))
+                }
+                Ok((auth_session, iuser)) => {
+                    AuthSession::touch(auth_session.id, &connection).map_err(query_rejection)?;
+                    Ok((iuser, auth_session))
+                }
                 Err(_) => Err(reject::custom(AuthorizationError::InvalidToken)),
             }
         }
         None => Err(reject::custom(AuthorizationError::NoToken)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::common::offline_session;
+
+    fn namespaces(ids: &[i64]) -> Vec<Namespace> {
+        ids.iter()
+            .map(|&id| Namespace {
+                id,
+                name: format!("namespace{}", id),
+                owner_id: 1,
+            })
+            .collect()
+    }
+
+    fn is_unauthorized(rejection: Rejection) -> bool {
+        matches!(
+            rejection.find::<AuthorizationError>(),
+            Some(AuthorizationError::Unauthorized)
+        )
+    }
+
+    fn api_key(namespace_ids: &[i64]) -> ApiKey {
+        ApiKey {
+            id: 1,
+            internal_user_id: 1,
+            name: String::from("service"),
+            prefix: String::from("idk_"),
+            key: String::new(),
+            scopes: vec![Scope::InternalManage.to_string()],
+            namespace_ids: namespace_ids.to_vec(),
+            created_on: chrono::Utc::now(),
+            expires_on: None,
+            last_used: None,
+        }
+    }
+
+    fn is_required(rejection: Rejection) -> bool {
+        rejection.find::<InputError>().is_some()
+    }
+
+    #[test]
+    fn single_namespace_needs_no_header() {
+        let namespace = resolve_namespace(None, namespaces(&[3]), |_| true)
+            .ok()
+            .unwrap();
+        assert_eq!(namespace.id, 3);
+        let namespace = resolve_namespace(Some(3), namespaces(&[3]), |_| true)
+            .ok()
+            .unwrap();
+        assert_eq!(namespace.id, 3);
+    }
+
+    #[test]
+    fn several_namespaces_need_the_header() {
+        let rejection = resolve_namespace(None, namespaces(&[3, 4]), |_| true)
+            .err()
+            .unwrap();
+        assert!(is_required(rejection));
+        let namespace = resolve_namespace(Some(4), namespaces(&[3, 4]), |_| true)
+            .ok()
+            .unwrap();
+        assert_eq!(namespace.id, 4);
+        let rejection = resolve_namespace(None, namespaces(&[]), |_| true)
+            .err()
+            .unwrap();
+        assert!(is_required(rejection));
+    }
+
+    #[test]
+    fn only_member_namespaces_resolve() {
+        let rejection = resolve_namespace(Some(5), namespaces(&[3, 4]), |_| true)
+            .err()
+            .unwrap();
+        assert!(is_unauthorized(rejection));
+    }
+
+    #[test]
+    fn api_key_restrictions_apply() {
+        let rejection = resolve_namespace(Some(3), namespaces(&[3, 4]), |id| id == 4)
+            .err()
+            .unwrap();
+        assert!(is_unauthorized(rejection));
+        // Restricting the key leaves a single namespace to pick
+        let namespace = resolve_namespace(None, namespaces(&[3, 4]), |id| id == 4)
+            .ok()
+            .unwrap();
+        assert_eq!(namespace.id, 4);
+    }
+
+    #[test]
+    fn restricted_api_keys_cannot_reach_beyond_namespaces() {
+        assert!(check_unrestricted(None).is_ok());
+        assert!(check_unrestricted(Some(&api_key(&[]))).is_ok());
+        let rejection = check_unrestricted(Some(&api_key(&[3]))).err().unwrap();
+        assert!(is_unauthorized(rejection));
+    }
+
+    // Refreshing and logging out act on the session of the token, which API
+    // keys do not have.
+    #[tokio::test]
+    async fn session_routes_need_a_session_token() {
+        let filter = with_auth_session(Arc::new(DatabaseConfig::default()), offline_session())
+            .map(|_iuser, _auth_session| "ok");
+        let rejection = warp::test::request().filter(&filter).await.err().unwrap();
+        assert!(matches!(
+            rejection.find::<AuthorizationError>(),
+            Some(AuthorizationError::NoToken)
+        ));
+        let rejection = warp::test::request()
+            .header("authorization", format!("{}abcdef", API_KEY_PREFIX))
+            .filter(&filter)
+            .await
+            .err()
+            .unwrap();
+        assert!(is_unauthorized(rejection));
+    }
+}
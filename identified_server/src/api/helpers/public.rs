@@ -0,0 +1,114 @@
+use warp::Reply;
+
+use crate::database::models::internal_user::{InternalUser, PublicInternalUser};
+
+// Internal users only ever leave the server through these, so the password
+// hash and other secrets never reach a response.
+
+pub fn reply_all(iusers: Vec<InternalUser>) -> impl Reply {
+    let results: Vec<PublicInternalUser> =
+        iusers.into_iter().map(PublicInternalUser::from).collect();
+    warp::reply::json(&results)
+}
+
+// Along with the ETag to send back as `If-Match`.
+pub fn reply_one(iuser: InternalUser) -> impl Reply {
+    let public = PublicInternalUser::from(iuser);
+    let etag = public.etag();
+    warp::reply::with_header(warp::reply::json(&public), "etag", etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::helpers::subscription::PermissionUpdate;
+    use chrono::Utc;
+    use warp::Filter;
+
+    fn internal_user() -> InternalUser {
+        InternalUser {
+            id: 1,
+            name: String::from("root"),
+            email: String::from("root@admin.com"),
+            password: String::from("$argon2id$v=19$m=19456,t=2,p=1$secret-salt$secret-hash"),
+            created_on: Utc::now(),
+            last_login: Some(Utc::now()),
+            scopes: vec![String::from("internal:manage")],
+            totp_required: false,
+            email_verified_on: None,
+        }
+    }
+
+    fn keys(value: &serde_json::Value) -> Vec<String> {
+        match value {
+            serde_json::Value::Object(map) => map
+                .iter()
+                .flat_map(|(key, value)| {
+                    let mut keys = keys(value);
+                    keys.push(key.clone());
+                    keys
+                })
+                .collect(),
+            serde_json::Value::Array(values) => values.iter().flat_map(keys).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    async fn body_of<F>(filter: &F) -> String
+    where
+        F: Filter + 'static,
+        F::Extract: Reply + Send,
+    {
+        let response = warp::test::request().path("/").reply(filter).await;
+        assert_eq!(response.status(), 200);
+        String::from_utf8(response.body().to_vec()).unwrap()
+    }
+
+    fn assert_no_secrets(body: &str) {
+        let value: serde_json::Value = serde_json::from_str(body).unwrap();
+        for key in keys(&value) {
+            assert!(
+                !["password", "salt", "token"]
+                    .iter()
+                    .any(|secret| key.contains(secret)),
+                "`{}` leaked into {}",
+                key,
+                body
+            );
+        }
+        for secret in ["secret-salt", "secret-hash"].iter() {
+            assert!(!body.contains(secret), "`{}` leaked into {}", secret, body);
+        }
+    }
+
+    // `/internal`, `/me`, `/email/confirm`, `/setup` and namespace members
+    // all answer with these.
+    #[tokio::test]
+    async fn responses_have_no_secrets() {
+        let one = warp::any().map(|| reply_one(internal_user()));
+        let body = body_of(&one).await;
+        assert!(body.contains("root@admin.com"));
+        assert_no_secrets(&body);
+
+        let all = warp::any().map(|| reply_all(vec![internal_user(), internal_user()]));
+        let body = body_of(&all).await;
+        assert!(body.contains("root@admin.com"));
+        assert_no_secrets(&body);
+    }
+
+    // What subscribed sockets are sent.
+    #[test]
+    fn permission_updates_have_no_secrets() {
+        let update = PermissionUpdate {
+            user_id: 1,
+            permission_id: 2,
+            allowed: true,
+        };
+        let body = serde_json::to_string(&update).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let mut found = keys(&value);
+        found.sort();
+        assert_eq!(found, vec!["allowed", "permission_id", "user_id"]);
+        assert_no_secrets(&body);
+    }
+}
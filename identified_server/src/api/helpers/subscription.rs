@@ -0,0 +1,186 @@
+use diesel::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use warp::Filter;
+
+use crate::database::models::check::Decision;
+use crate::database::models::permission::Permission;
+
+type PermissionHashMap<T> = Arc<Mutex<HashMap<i64, HashMap<usize, T>>>>;
+pub type PermissionSender = mpsc::UnboundedSender<Result<PermissionUpdate, warp::Error>>;
+pub type PermissionStreams = PermissionHashMap<PermissionSender>;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct PermissionUpdate {
+    pub user_id: i64,
+    pub permission_id: i64,
+    pub allowed: bool,
+}
+
+pub fn with_streams(
+    permission_streams: PermissionStreams,
+) -> impl Filter<Extract = (PermissionStreams,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || permission_streams.clone())
+}
+
+// Permissions of `by_namespace_id` that at least one socket is subscribed to.
+fn watched_permissions(
+    by_namespace_id: i64,
+    permission_streams: &PermissionStreams,
+    connection: &PgConnection,
+) -> Result<Vec<i64>, diesel::result::Error> {
+    let watched: Vec<i64> = permission_streams
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, streams)| !streams.is_empty())
+        .map(|(permission_id, _)| *permission_id)
+        .collect();
+    if watched.is_empty() {
+        return Ok(watched);
+    }
+    Ok(
+        Permission::find_by_ids(by_namespace_id, &watched, connection)?
+            .into_iter()
+            .map(|p| p.id)
+            .collect(),
+    )
+}
+
+fn decisions(
+    by_namespace_id: i64,
+    user_ids: Option<&[i64]>,
+    permission_ids: &[i64],
+    connection: &PgConnection,
+) -> Result<HashMap<(i64, i64), bool>, diesel::result::Error> {
+    Ok(
+        Decision::allowed(by_namespace_id, user_ids, permission_ids, connection)?
+            .into_iter()
+            .map(|a| ((a.user_id, a.permission_id), a.allowed))
+            .collect(),
+    )
+}
+
+// Pairs missing on either side, because the user or the permission does not
+// exist (anymore), count as not allowed.
+fn changes(
+    before: &HashMap<(i64, i64), bool>,
+    after: &HashMap<(i64, i64), bool>,
+) -> Vec<PermissionUpdate> {
+    let allowed =
+        |decisions: &HashMap<(i64, i64), bool>, key: &(i64, i64)| decisions.get(key) == Some(&true);
+    let keys: HashSet<&(i64, i64)> = before.keys().chain(after.keys()).collect();
+    let mut updates: Vec<PermissionUpdate> = keys
+        .into_iter()
+        .filter(|key| allowed(before, key) != allowed(after, key))
+        .map(|&(user_id, permission_id)| PermissionUpdate {
+            user_id,
+            permission_id,
+            allowed: allowed(after, &(user_id, permission_id)),
+        })
+        .collect();
+    updates.sort_by_key(|u| (u.permission_id, u.user_id));
+    updates
+}
+
+pub fn publish(permission_streams: &PermissionStreams, updates: Vec<PermissionUpdate>) {
+    let permission_streams = permission_streams.lock().unwrap();
+    for update in updates {
+        if let Some(streams) = permission_streams.get(&update.permission_id) {
+            for sender in streams.values() {
+                // The receiving end is dropped once the socket disconnects, it
+                // is removed from the map shortly after.
+                let _ = sender.send(Ok(update));
+            }
+        }
+    }
+}
+
+// Runs `change` and pushes a `PermissionUpdate` for every watched permission
+// whose effective decision changed for one of `user_ids`, or for any user of
+// the namespace without them. The decisions before and after are taken in the
+// same transaction as the change, and only published once it is committed.
+pub fn publishing<T>(
+    by_namespace_id: i64,
+    user_ids: Option<&[i64]>,
+    permission_streams: &PermissionStreams,
+    connection: &PgConnection,
+    change: impl FnOnce() -> Result<T, diesel::result::Error>,
+) -> Result<T, diesel::result::Error> {
+    let (result, updates) = connection.transaction::<_, diesel::result::Error, _>(|| {
+        let watched = watched_permissions(by_namespace_id, permission_streams, connection)?;
+        if watched.is_empty() || user_ids.is_some_and(|ids| ids.is_empty()) {
+            return Ok((change()?, Vec::new()));
+        }
+        let before = decisions(by_namespace_id, user_ids, &watched, connection)?;
+        let result = change()?;
+        let after = decisions(by_namespace_id, user_ids, &watched, connection)?;
+        Ok((result, changes(&before, &after)))
+    })?;
+    publish(permission_streams, updates);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(user_id: i64, permission_id: i64, allowed: bool) -> PermissionUpdate {
+        PermissionUpdate {
+            user_id,
+            permission_id,
+            allowed,
+        }
+    }
+
+    #[test]
+    fn unchanged_decisions_are_not_published() {
+        let before: HashMap<(i64, i64), bool> =
+            vec![((1, 1), true), ((1, 2), false)].into_iter().collect();
+        assert!(changes(&before, &before.clone()).is_empty());
+    }
+
+    #[test]
+    fn changed_decisions_are_published_in_order() {
+        let before: HashMap<(i64, i64), bool> = vec![
+            ((2, 1), false),
+            ((1, 2), true),
+            ((1, 1), false),
+            ((3, 1), true),
+        ]
+        .into_iter()
+        .collect();
+        let after: HashMap<(i64, i64), bool> = vec![
+            ((2, 1), true),
+            ((1, 2), false),
+            ((1, 1), true),
+            ((3, 1), true),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            changes(&before, &after),
+            vec![update(1, 1, true), update(2, 1, true), update(1, 2, false)]
+        );
+    }
+
+    #[test]
+    fn removed_users_and_permissions_are_denied() {
+        let before: HashMap<(i64, i64), bool> =
+            vec![((1, 1), true), ((1, 2), false), ((2, 1), true)]
+                .into_iter()
+                .collect();
+        let after: HashMap<(i64, i64), bool> = vec![((2, 1), true)].into_iter().collect();
+        assert_eq!(changes(&before, &after), vec![update(1, 1, false)]);
+    }
+
+    #[test]
+    fn new_pairs_are_only_published_when_allowed() {
+        let before = HashMap::new();
+        let after: HashMap<(i64, i64), bool> =
+            vec![((1, 1), false), ((2, 1), true)].into_iter().collect();
+        assert_eq!(changes(&before, &after), vec![update(2, 1, true)]);
+    }
+}
@@ -0,0 +1,308 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::subscription::*,
+    database::models::internal_user::InternalUser,
+    database::models::namespace::Namespace,
+    database::models::tree::{moved_path, renamed_path, MoveNode, SubmitNode, Tree},
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::scope::Scope,
+};
+
+// The routes roles and permissions share, reading with `read` and changing
+// with `write`. Renaming, moving and deleting publish the decisions they
+// change.
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter<T: Tree>(
+        read: Scope,
+        write: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter::<T>(read, db_config.clone(), session.clone())
+                .or(find_filter::<T>(read, db_config.clone(), session.clone()))
+                .or(subtree_filter::<T>(
+                    read,
+                    db_config.clone(),
+                    session.clone(),
+                ))
+                .or(create_filter::<T>(
+                    write,
+                    db_config.clone(),
+                    session.clone(),
+                ))
+                .or(update_filter::<T>(
+                    write,
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(move_filter::<T>(
+                    write,
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(delete_filter::<T>(
+                    write,
+                    db_config,
+                    session,
+                    permission_streams,
+                )),
+        )
+    }
+
+    pub fn all_filter<T: Tree>(
+        read: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_namespace(read, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::all::<T>)
+    }
+
+    pub fn find_filter<T: Tree>(
+        read: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with_namespace(read, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::find::<T>)
+    }
+
+    pub fn subtree_filter<T: Tree>(
+        read: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("subtree"))
+            .and(end())
+            .and(with_namespace(read, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::subtree::<T>)
+    }
+
+    pub fn create_filter<T: Tree>(
+        write: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_member(write, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::create::<T>)
+    }
+
+    pub fn update_filter<T: Tree>(
+        write: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::patch())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(write, db_config, session.clone()))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::update::<T>)
+    }
+
+    pub fn move_filter<T: Tree>(
+        write: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("move"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(write, db_config, session.clone()))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::move_subtree::<T>)
+    }
+
+    pub fn delete_filter<T: Tree>(
+        write: Scope,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(warp::query::<DeleteOptions>())
+            .and(with_namespace(write, db_config, session.clone()))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::delete::<T>)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+    use crate::utils::common::WithId;
+
+    pub async fn all<T: Tree>(
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = T::all(namespace.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn find<T: Tree>(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result = T::find_by_id(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    pub async fn subtree<T: Tree>(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = T::subtree(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn create<T: Tree>(
+        submitted: SubmitNode,
+        iuser: InternalUser,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        validate_ltree("name", &submitted.name)?;
+        let connection = get_connection(session)?;
+        let result =
+            T::create(namespace.id, iuser.id, submitted, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    // Renaming a node renames its whole subtree.
+    pub async fn update<T: Tree>(
+        submitted: WithId<SubmitNode>,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        validate_ltree("name", &submitted.contained.name)?;
+        let connection = get_connection(session)?;
+        let old =
+            T::find_by_id(namespace.id, submitted.id, &connection).map_err(query_rejection)?;
+        let new_name = renamed_path(old.name(), submitted.contained.name).ok_or_else(|| {
+            warp::reject::custom(InputError::new("name", ValidationError::Invalid))
+        })?;
+        let affected =
+            T::affected_users(namespace.id, old.id(), &connection).map_err(query_rejection)?;
+        let result = publishing(
+            namespace.id,
+            affected.as_deref(),
+            &permission_streams,
+            &connection,
+            || T::move_subtree(namespace.id, old.id(), new_name, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    pub async fn move_subtree<T: Tree>(
+        by_id: i64,
+        submitted: MoveNode,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let old = T::find_by_id(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        let parent = match submitted.parent_id {
+            Some(parent_id) => {
+                Some(T::find_by_id(namespace.id, parent_id, &connection).map_err(query_rejection)?)
+            }
+            None => None,
+        };
+        let new_name =
+            moved_path(old.name(), parent.as_ref().map(|p| p.name())).ok_or_else(|| {
+                warp::reject::custom(InputError::new("parent_id", ValidationError::Invalid))
+            })?;
+        let affected =
+            T::affected_users(namespace.id, old.id(), &connection).map_err(query_rejection)?;
+        let result = publishing(
+            namespace.id,
+            affected.as_deref(),
+            &permission_streams,
+            &connection,
+            || T::move_subtree(namespace.id, old.id(), new_name, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    // Refuses to delete nodes that are still in use, unless the caller
+    // explicitly asks to cascade.
+    pub async fn delete<T: Tree>(
+        by_id: i64,
+        options: DeleteOptions,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let in_use = T::in_use(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        if in_use && !options.cascade {
+            return Err(warp::reject::custom(InputError::new(
+                "id",
+                ValidationError::InUse,
+            )));
+        }
+        let affected =
+            T::affected_users(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        let results = publishing(
+            namespace.id,
+            affected.as_deref(),
+            &permission_streams,
+            &connection,
+            || T::delete(namespace.id, by_id, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
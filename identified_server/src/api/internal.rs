@@ -2,10 +2,13 @@ use std::sync::Arc;
 use warp::{path::end, Filter, Rejection, Reply};
 
 use crate::{
-    database::models::internal_user::InternalUser,
+    api::helpers::authorization::with_authentication,
+    api::helpers::public::{reply_all, reply_one},
+    database::models::internal_user::{InternalUser, PublicInternalUser},
     database::{get_connection, DatabaseConfig},
     utils::common::*,
     utils::errors::*,
+    utils::scope::Scope,
 };
 
 pub mod filters {
@@ -17,9 +20,17 @@ pub mod filters {
     ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
         warp::any().and(
             all_filter(session.clone())
+                .or(find_filter(session.clone()))
                 .or(create_filter(db_config.clone(), session.clone()))
                 .or(update_filter(db_config.clone(), session.clone()))
-                .or(delete_filter(session.clone())),
+                .or(scopes_filter(session.clone()))
+                .or(sessions_filter(session.clone()))
+                .or(revoke_sessions_filter(session.clone()))
+                .or(api_keys_filter(session.clone()))
+                .or(totp_required_filter(session.clone()))
+                .or(reset_totp_filter(session.clone()))
+                .or(revoke_api_keys_filter(session.clone()))
+                .or(delete_filter(db_config, session)),
         )
     }
 
@@ -33,6 +44,17 @@ pub mod filters {
             .and_then(handlers::all)
     }
 
+    pub fn find_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::find)
+    }
+
     pub fn create_filter(
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
@@ -53,69 +75,303 @@ pub mod filters {
     ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
         warp::any()
             .and(warp::patch())
+            .and(warp::path::param::<i64>())
+            .and(end())
             .and(warp::body::content_length_limit(1024 * 16))
             .and(warp::body::json())
+            .and(warp::header::optional::<String>("if-match"))
             .and(with_db_config(db_config))
             .and(with(session))
-            .and(end())
             .and_then(handlers::update)
     }
 
-    pub fn delete_filter(
+    pub fn scopes_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::put())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("scopes"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with(session))
+            .and_then(handlers::scopes)
+    }
+
+    pub fn sessions_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("sessions"))
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::sessions)
+    }
+
+    pub fn revoke_sessions_filter(
         session: Arc<Session>,
     ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
         warp::any()
             .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("sessions"))
+            .and(end())
             .and(with(session))
+            .and_then(handlers::revoke_sessions)
+    }
+
+    pub fn api_keys_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("apikeys"))
             .and(end())
-            .and_then(handlers::all)
+            .and(with(session))
+            .and_then(handlers::api_keys)
+    }
+
+    pub fn revoke_api_keys_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("apikeys"))
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::revoke_api_keys)
+    }
+
+    pub fn totp_required_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::put())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("totp"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with(session))
+            .and_then(handlers::totp_required)
+    }
+
+    pub fn reset_totp_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("totp"))
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::reset_totp)
+    }
+
+    pub fn delete_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            // `internal:manage` is already required for all of /internal, this
+            // only tells who inherits what the internal user owns
+            .and(with_authentication(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::delete)
     }
 }
 
 pub mod handlers {
     use super::*;
-    use crate::database::models::internal_user::SubmitInternalUser;
-    use crate::utils::common::WithId;
-    use crate::utils::errors::DbError::*;
+    use crate::database::models::api_key::{ApiKey, PublicApiKey};
+    use crate::database::models::internal_user::{
+        PatchInternalUser, SubmitNewInternalUser, SubmitScopes,
+    };
+    use crate::database::models::session::{AuthSession, PublicAuthSession};
+    use crate::database::models::totp::{SubmitTotpRequired, Totp};
     use http;
 
     pub async fn all(session: Arc<Session>) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        let results = InternalUser::all(&connection)
-            .map_err(|e| warp::reject::custom(DbError::DatabaseQueryError(format!("{}", e))))?;
-        Ok(warp::reply::json(&results))
+        let results = InternalUser::all(&connection).map_err(query_rejection)?;
+        Ok(reply_all(results))
+    }
+
+    pub async fn find(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result = InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        Ok(reply_one(result))
     }
 
     pub async fn create(
-        submitted: SubmitInternalUser,
+        submitted: SubmitNewInternalUser,
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
     ) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        InternalUser::create(submitted, false, db_config, &connection)
-            .map_err(|e| warp::reject::custom(DatabaseQueryError(format!("{}", e))))?;
+        let scopes = submitted.scopes.unwrap_or_else(|| Scope::DEFAULT.to_vec());
+        InternalUser::create(submitted.iuser, &scopes, db_config, &connection)
+            .map_err(query_rejection)?;
         Ok(http::StatusCode::OK)
     }
 
+    // Only the given fields change. With `If-Match` the update is refused
+    // when the internal user changed since the client read its ETag.
     pub async fn update(
-        submitted: WithId<SubmitInternalUser>,
+        by_id: i64,
+        submitted: PatchInternalUser,
+        if_match_header: Option<String>,
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
     ) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        let results =
-            InternalUser::update(submitted.id, submitted.contained, db_config, &connection)
-                .map_err(|e| warp::reject::custom(DbError::DatabaseQueryError(format!("{}", e))))?;
+        let precondition = |current: &InternalUser| match &if_match_header {
+            Some(header) => if_match(header, &PublicInternalUser::from(current.clone()).etag()),
+            None => true,
+        };
+        let result = InternalUser::update(by_id, submitted, precondition, db_config, &connection)
+            .map_err(query_rejection)?
+            .ok_or_else(|| {
+                warp::reject::custom(InputError::new("if-match", ValidationError::Outdated))
+            })?;
+        Ok(reply_one(result))
+    }
+
+    pub async fn scopes(
+        by_id: i64,
+        submitted: SubmitScopes,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result = InternalUser::set_scopes(by_id, &submitted.scopes, &connection)
+            .map_err(query_rejection)?;
+        Ok(reply_one(result))
+    }
+
+    pub async fn sessions(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        let results: Vec<PublicAuthSession> = AuthSession::all(by_id, &connection)
+            .map_err(query_rejection)?
+            .into_iter()
+            .map(|s| PublicAuthSession::new(s, None))
+            .collect();
         Ok(warp::reply::json(&results))
     }
 
-    pub async fn delete(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+    // Logs the internal user out everywhere.
+    pub async fn revoke_sessions(
+        by_id: i64,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        let results = InternalUser::delete(by_id, &connection)
-            .map_err(|e| warp::reject::custom(DbError::DatabaseQueryError(format!("{}", e))))?;
+        InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        let results = AuthSession::revoke_all(by_id, None, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn api_keys(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        let results: Vec<PublicApiKey> = ApiKey::all(by_id, &connection)
+            .map_err(query_rejection)?
+            .into_iter()
+            .map(PublicApiKey::from)
+            .collect();
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn revoke_api_keys(
+        by_id: i64,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        let results = ApiKey::revoke_all(by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    // Internal users that are required to use two-factor authentication are
+    // limited to enrolling until they do.
+    pub async fn totp_required(
+        by_id: i64,
+        submitted: SubmitTotpRequired,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result =
+            Totp::set_required(by_id, submitted.required, &connection).map_err(query_rejection)?;
+        Ok(reply_one(result))
+    }
+
+    // For internal users that lost their authenticator and recovery codes.
+    pub async fn reset_totp(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        InternalUser::find_by_id(by_id, &connection).map_err(query_rejection)?;
+        let results = Totp::disable(by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    // What the internal user owns goes to the admin deleting them. Admins
+    // delete their own account through `DELETE me`, and the last one able to
+    // manage internal users cannot be deleted.
+    pub async fn delete(
+        by_id: i64,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        if by_id == iuser.id {
+            return Err(warp::reject::custom(InputError::new(
+                "id",
+                ValidationError::Invalid,
+            )));
+        }
+        let connection = get_connection(session)?;
+        let results = InternalUser::delete_with_heir(by_id, iuser.id, &connection)
+            .map_err(query_rejection)?
+            .ok_or_else(|| warp::reject::custom(InputError::new("id", ValidationError::InUse)))?;
         Ok(warp::reply::json(&results))
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn internal_user() -> InternalUser {
+        InternalUser {
+            id: 1,
+            name: String::from("root"),
+            email: String::from("root@admin.com"),
+            password: String::from("$argon2id$v=19$m=19456,t=2,p=1$secret-salt$secret-hash"),
+            created_on: Utc::now(),
+            last_login: Some(Utc::now()),
+            scopes: vec![String::from("internal:manage")],
+            totp_required: false,
+            email_verified_on: None,
+        }
+    }
+
+    #[test]
+    fn etag_follows_the_representation() {
+        let etag = PublicInternalUser::from(internal_user()).etag();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert!(if_match(&etag, &etag));
+        assert!(if_match(&format!("\"other\", {}", etag), &etag));
+        assert!(if_match("*", &etag));
+        assert!(!if_match(&format!("W/{}", etag), &etag));
+
+        let mut renamed = internal_user();
+        renamed.name = String::from("admin");
+        assert!(!if_match(&etag, &PublicInternalUser::from(renamed).etag()));
+    }
+}
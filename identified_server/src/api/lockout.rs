@@ -0,0 +1,93 @@
+use chrono::Utc;
+use diesel::pg::PgConnection;
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    database::get_connection, database::models::lockout::Lockout, utils::common::*,
+    utils::errors::*,
+};
+
+// Refuses the attempt while any of `subjects` is locked out.
+pub fn check_locked(subjects: &[(&str, &str)], connection: &PgConnection) -> Result<(), Rejection> {
+    match Lockout::locked_until(subjects, connection).map_err(query_rejection)? {
+        Some(until) => {
+            let seconds = (until - Utc::now()).num_seconds() + 1;
+            Err(warp::reject::custom(AuthenticationError::TooManyAttempts(
+                seconds,
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter(session.clone())
+                .or(clear_filter(session.clone()))
+                .or(clear_all_filter(session)),
+        )
+    }
+
+    pub fn all_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::all)
+    }
+
+    pub fn clear_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::clear)
+    }
+
+    pub fn clear_all_filter(
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(end())
+            .and(with(session))
+            .and_then(handlers::clear_all)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Every email and IP address with recent failed logins, whether it is
+    // currently locked out or not.
+    pub async fn all(session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = Lockout::all(&connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    // Forgets the failed logins of one email or IP address, lifting any
+    // lockout.
+    pub async fn clear(by_id: i64, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = Lockout::delete(by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn clear_all(session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = Lockout::delete_all(&connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
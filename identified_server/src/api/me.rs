@@ -0,0 +1,240 @@
+use diesel::pg::PgConnection;
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::public::reply_one,
+    api::lockout::check_locked,
+    database::models::email_token::{EmailToken, EMAIL_VERIFICATION},
+    database::models::internal_user::{
+        InternalUser, SelfDeletion, SubmitPassword, SubmitPasswordChange, SubmitProfile,
+    },
+    database::models::lockout::{account_subject, Lockout, ACCOUNT},
+    database::models::session::AuthSession,
+    database::{get_connection, DatabaseConfig},
+    mailer::Outbox,
+    utils::common::*,
+    utils::errors::*,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            profile_filter(db_config.clone(), session.clone())
+                .or(update_filter(db_config.clone(), session.clone(), outbox))
+                .or(password_filter(db_config.clone(), session.clone()))
+                .or(delete_filter(db_config, session)),
+        )
+    }
+
+    pub fn profile_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_login(db_config, session))
+            .and_then(handlers::profile)
+    }
+
+    pub fn update_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::patch())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and(with(outbox))
+            .and_then(handlers::update)
+    }
+
+    pub fn password_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::put())
+            .and(warp::path("password"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_auth_session(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::change_password)
+    }
+
+    pub fn delete_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(end())
+            .and(warp::query::<DeleteOptions>())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::delete)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // A stolen session should not be enough to take over the account, so
+    // the password is asked for again. Wrong guesses count towards the
+    // lockout of the account like failed logins do.
+    fn check_password(
+        iuser: &InternalUser,
+        field: &str,
+        password: &str,
+        db_config: &DatabaseConfig,
+        connection: &PgConnection,
+    ) -> Result<(), Rejection> {
+        let account = account_subject(&iuser.email);
+        let subjects = [(ACCOUNT, account.as_str())];
+        check_locked(&subjects, connection)?;
+        if iuser.verify_password(password) {
+            return Ok(());
+        }
+        Lockout::record_failure(&subjects, &db_config.lockout, connection)
+            .map_err(query_rejection)?;
+        Err(warp::reject::custom(InputError::new(
+            field,
+            ValidationError::Invalid,
+        )))
+    }
+
+    fn required(field: &str, value: &Option<String>) -> Result<(), Rejection> {
+        match value.as_deref() {
+            Some(value) if value.trim().is_empty() => Err(warp::reject::custom(InputError::new(
+                field,
+                ValidationError::Required,
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn profile(iuser: InternalUser) -> Result<impl Reply, Rejection> {
+        Ok(reply_one(iuser))
+    }
+
+    // A new email is unverified until the token mailed to it is confirmed.
+    pub async fn update(
+        submitted: SubmitProfile,
+        iuser: InternalUser,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> Result<impl Reply, Rejection> {
+        required("name", &submitted.name)?;
+        required("email", &submitted.email)?;
+        let connection = get_connection(session)?;
+        let email_changed = submitted
+            .email
+            .as_ref()
+            .is_some_and(|email| *email != iuser.email);
+        if email_changed {
+            let password = submitted.password.as_deref().ok_or_else(|| {
+                warp::reject::custom(InputError::new("password", ValidationError::Required))
+            })?;
+            check_password(&iuser, "password", password, &db_config, &connection)?;
+        }
+        let result =
+            InternalUser::update_profile(iuser.id, submitted.name, submitted.email, &connection)
+                .map_err(query_rejection)?;
+        if email_changed {
+            let (email_token, token) =
+                EmailToken::create(&result, EMAIL_VERIFICATION, db_config, &connection)
+                    .map_err(query_rejection)?;
+            // The change stands, another mail can be asked for at `email/verify`
+            if let Err(e) = outbox.email_verification(&result.email, &token, email_token.expires_on)
+            {
+                log::error!("{}", e);
+            }
+        }
+        Ok(reply_one(result))
+    }
+
+    // Logs out every other session, the current one stays valid.
+    pub async fn change_password(
+        submitted: SubmitPasswordChange,
+        iuser: InternalUser,
+        auth_session: AuthSession,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        if submitted.password.is_empty() {
+            return Err(warp::reject::custom(InputError::new(
+                "password",
+                ValidationError::Required,
+            )));
+        }
+        let connection = get_connection(session)?;
+        check_password(
+            &iuser,
+            "current_password",
+            &submitted.current_password,
+            &db_config,
+            &connection,
+        )?;
+        let result = InternalUser::set_password(
+            iuser.id,
+            &submitted.password,
+            Some(auth_session.id),
+            db_config,
+            &connection,
+        )
+        .map_err(query_rejection)?;
+        Ok(reply_one(result))
+    }
+
+    // The last internal user able to manage the others cannot leave. See
+    // `InternalUser::delete_self` for what happens to what they own.
+    pub async fn delete(
+        options: DeleteOptions,
+        submitted: SubmitPassword,
+        iuser: InternalUser,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        check_password(
+            &iuser,
+            "password",
+            &submitted.password,
+            &db_config,
+            &connection,
+        )?;
+        match InternalUser::delete_self(iuser.id, options.cascade, &connection)
+            .map_err(query_rejection)?
+        {
+            SelfDeletion::Deleted(results) => Ok(warp::reply::json(&results)),
+            SelfDeletion::LastAdmin => Err(warp::reject::custom(InputError::new(
+                "id",
+                ValidationError::InUse,
+            ))),
+            SelfDeletion::NamespaceInUse => Err(warp::reject::custom(InputError::new(
+                "namespace",
+                ValidationError::InUse,
+            ))),
+        }
+    }
+}
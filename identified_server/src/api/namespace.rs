@@ -0,0 +1,316 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::public::reply_all,
+    api::helpers::subscription::*,
+    database::models::internal_user::InternalUser,
+    database::models::namespace::{Namespace, SubmitNamespace},
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::scope::Scope,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter(db_config.clone(), session.clone())
+                .or(create_filter(db_config.clone(), session.clone()))
+                .or(delete_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams,
+                ))
+                .or(members_filter(db_config.clone(), session.clone()))
+                .or(add_members_filter(db_config.clone(), session.clone()))
+                .or(remove_members_filter(db_config, session)),
+        )
+    }
+
+    pub fn all_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_authentication(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::all)
+    }
+
+    pub fn create_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_authorization(
+                Scope::NamespacesManage,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and_then(handlers::create)
+    }
+
+    pub fn delete_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(warp::query::<DeleteOptions>())
+            .and(toss(with_authorization(
+                Scope::NamespacesManage,
+                db_config,
+                session.clone(),
+            )))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::delete)
+    }
+
+    pub fn members_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("members"))
+            .and(end())
+            .and(with_authentication(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::members)
+    }
+
+    pub fn add_members_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("members"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(toss(with_authorization(
+                Scope::NamespacesManage,
+                db_config,
+                session.clone(),
+            )))
+            .and(with(session))
+            .and_then(handlers::add_members)
+    }
+
+    pub fn remove_members_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("members"))
+            .and(end())
+            .and(warp::query::<IdsQuery>())
+            .and(toss(with_authorization(
+                Scope::NamespacesManage,
+                db_config,
+                session.clone(),
+            )))
+            .and(with(session))
+            .and_then(handlers::remove_members)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Internal users managing namespaces see every one of them, everyone else
+    // only the ones they are a member of.
+    pub async fn all(iuser: InternalUser, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = match iuser.has_scope(Scope::NamespacesManage) {
+            true => Namespace::all(&connection),
+            false => Namespace::of_member(iuser.id, &connection),
+        }
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn create(
+        submitted: SubmitNamespace,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        if submitted.name.trim().is_empty() {
+            return Err(warp::reject::custom(InputError::new(
+                "name",
+                ValidationError::Required,
+            )));
+        }
+        let connection = get_connection(session)?;
+        let result =
+            Namespace::create(iuser.id, submitted, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    // Refuses to delete namespaces that still hold users, roles or
+    // permissions, unless the caller explicitly asks to cascade.
+    pub async fn delete(
+        by_id: i64,
+        options: DeleteOptions,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let has_contents = Namespace::has_contents(by_id, &connection).map_err(query_rejection)?;
+        if has_contents && !options.cascade {
+            return Err(warp::reject::custom(InputError::new(
+                "id",
+                ValidationError::InUse,
+            )));
+        }
+        // Every user of the namespace loses the permissions they had
+        let results = publishing(by_id, None, &permission_streams, &connection, || {
+            Namespace::delete(by_id, &connection)
+        })
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn members(
+        by_id: i64,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        if !iuser.has_scope(Scope::NamespacesManage) {
+            Namespace::find_for_member(iuser.id, by_id, &connection).map_err(query_rejection)?;
+        }
+        let results = Namespace::members(by_id, &connection).map_err(query_rejection)?;
+        Ok(reply_all(results))
+    }
+
+    pub async fn add_members(
+        by_id: i64,
+        submitted: SubmitIds,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results =
+            Namespace::add_members(by_id, &submitted.ids, &connection).map_err(query_rejection)?;
+        Ok(reply_all(results))
+    }
+
+    pub async fn remove_members(
+        by_id: i64,
+        submitted: IdsQuery,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = Namespace::remove_members(by_id, &submitted.ids, &connection)
+            .map_err(query_rejection)?;
+        Ok(reply_all(results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::{test_internal_user, PublicInternalUser};
+    use crate::database::models::session::test_token;
+    use crate::database::models::user::{SubmitUser, User};
+    use crate::database::test_session;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn deleting_a_namespace_with_contents_needs_cascade() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let (admin, member, token, member_token) = {
+            let connection = session.connection_pool.get().unwrap();
+            let admin = test_internal_user("namespace-admin@example.com", &connection);
+            let admin = InternalUser::set_scopes(admin.id, &Scope::ALL, &connection).unwrap();
+            let member = test_internal_user("namespace-member@example.com", &connection);
+            let token = test_token(admin.id, db_config.clone(), &connection);
+            let member_token = test_token(member.id, db_config.clone(), &connection);
+            (admin, member, token, member_token)
+        };
+        let filter = filters::main_filter(db_config, session.clone(), PermissionStreams::default())
+            .recover(handle_rejection);
+        let create = |name: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/")
+                .header("authorization", &token)
+                .json(&json!({ "name": name }))
+        };
+        let response = create("shared").reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let shared: Namespace = serde_json::from_slice(response.body()).unwrap();
+        let response = create("empty").reply(&filter).await;
+        let empty: Namespace = serde_json::from_slice(response.body()).unwrap();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}/members", shared.id))
+            .header("authorization", &token)
+            .json(&json!({ "ids": [member.id] }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let members: Vec<PublicInternalUser> = serde_json::from_slice(response.body()).unwrap();
+        let mut ids: Vec<i64> = members.iter().map(|m| m.id).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![admin.id, member.id]);
+
+        {
+            let connection = session.connection_pool.get().unwrap();
+            let submitted = SubmitUser { name: None };
+            User::create(shared.id, member.id, submitted, &connection).unwrap();
+        }
+        let delete = |token: &str, id: i64, query: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/{}{}", id, query))
+                .header("authorization", token)
+        };
+        let status = |request: warp::test::RequestBuilder| {
+            let filter = filter.clone();
+            async move { request.reply(&filter).await.status() }
+        };
+        // Members cannot delete it, only internal users managing namespaces
+        assert_eq!(status(delete(&member_token, shared.id, "")).await, 403);
+        assert_eq!(status(delete(&token, shared.id, "")).await, 409);
+        assert_eq!(
+            status(delete(&token, shared.id, "?cascade=true")).await,
+            200
+        );
+        assert_eq!(status(delete(&token, empty.id, "")).await, 200);
+
+        let connection = session.connection_pool.get().unwrap();
+        let left = Namespace::of_member(member.id, &connection).unwrap();
+        assert!(left.iter().all(|namespace| namespace.id != shared.id));
+        assert!(!Namespace::has_contents(shared.id, &connection).unwrap());
+    }
+}
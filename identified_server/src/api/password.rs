@@ -0,0 +1,209 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::lockout::check_locked,
+    database::models::email_token::{EmailToken, SubmitEmail, SubmitPasswordReset, PASSWORD_RESET},
+    database::models::internal_user::InternalUser,
+    database::models::lockout::{account_subject, Lockout, ACCOUNT, PASSWORD_RESET_MAIL},
+    database::{get_connection, DatabaseConfig},
+    mailer::Outbox,
+    utils::common::*,
+    utils::errors::*,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            forgot_filter(db_config.clone(), session.clone(), outbox)
+                .or(reset_filter(db_config, session)),
+        )
+    }
+
+    pub fn forgot_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("forgot"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and(with(outbox))
+            .and_then(handlers::forgot)
+    }
+
+    pub fn reset_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("reset"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::reset)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Answers the same whether or not the email exists, so it cannot be
+    // used to find out which emails do. Requests count against the email
+    // either way, and the lookup and mail happen after answering, so the
+    // response takes as long for both.
+    pub async fn forgot(
+        submitted: SubmitEmail,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session.clone())?;
+        let email = account_subject(&submitted.email);
+        let subjects = [(PASSWORD_RESET_MAIL, email.as_str())];
+        check_locked(&subjects, &connection)?;
+        Lockout::record_failure(&subjects, &db_config.lockout, &connection)
+            .map_err(query_rejection)?;
+        drop(connection);
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = send_password_reset(submitted.email, db_config, session, outbox) {
+                log::error!("{}", e);
+            }
+        });
+        Ok(http::StatusCode::ACCEPTED)
+    }
+
+    fn send_password_reset(
+        email: String,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        outbox: Arc<Outbox>,
+    ) -> Result<(), String> {
+        let connection = session
+            .connection_pool
+            .get()
+            .map_err(|e| format!("Could not send a password reset: {}", e))?;
+        let iuser = match InternalUser::find_by_email(email, &connection) {
+            Ok(iuser) => iuser,
+            Err(diesel::result::Error::NotFound) => return Ok(()),
+            Err(e) => return Err(format!("Could not send a password reset: {}", e)),
+        };
+        let (email_token, token) =
+            EmailToken::create(&iuser, PASSWORD_RESET, db_config, &connection)
+                .map_err(|e| format!("Could not send a password reset: {}", e))?;
+        outbox.password_reset(&iuser.email, &token, email_token.expires_on)
+    }
+
+    // Logs the internal user out everywhere and lifts a lockout of the
+    // account and its password reset mails, two-factor authentication still applies on the next login.
+    pub async fn reset(
+        submitted: SubmitPasswordReset,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        if submitted.password.is_empty() {
+            return Err(warp::reject::custom(InputError::new(
+                "password",
+                ValidationError::Required,
+            )));
+        }
+        let connection = get_connection(session)?;
+        let iuser = EmailToken::consume(
+            &submitted.token,
+            PASSWORD_RESET,
+            db_config.clone(),
+            &connection,
+        )
+        .map_err(query_rejection)?
+        .ok_or_else(|| warp::reject::custom(InputError::new("token", ValidationError::Invalid)))?;
+        InternalUser::set_password(iuser.id, &submitted.password, None, db_config, &connection)
+            .map_err(query_rejection)?;
+        let email = account_subject(&iuser.email);
+        Lockout::clear(ACCOUNT, &email, &connection).map_err(query_rejection)?;
+        Lockout::clear(PASSWORD_RESET_MAIL, &email, &connection).map_err(query_rejection)?;
+        Ok(http::StatusCode::OK)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MailConfig;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::test_session;
+    use crate::mailer::Message;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn reset_token_sets_a_new_password_once() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let iuser = test_internal_user(
+            "forgot@example.com",
+            &session.connection_pool.get().unwrap(),
+        );
+        let sent: Arc<Mutex<Vec<Message>>> = Arc::new(Mutex::new(Vec::new()));
+        let outbox = Outbox::new(Box::new(sent.clone()), &MailConfig::default());
+        let filter = filters::main_filter(
+            Arc::new(DatabaseConfig::default()),
+            session.clone(),
+            Arc::new(outbox),
+        )
+        .recover(handle_rejection);
+        let forgot = || {
+            warp::test::request()
+                .method("POST")
+                .path("/forgot")
+                .json(&SubmitEmail {
+                    email: iuser.email.clone(),
+                })
+        };
+        assert_eq!(forgot().reply(&filter).await.status(), 202);
+        assert_eq!(forgot().reply(&filter).await.status(), 429);
+
+        // The mail goes out after answering
+        for _ in 0..50 {
+            if !sent.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+        let token = {
+            let sent = sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            assert_eq!(sent[0].to, iuser.email);
+            sent[0].body.lines().nth(2).unwrap().to_string()
+        };
+        let reset = || {
+            warp::test::request()
+                .method("POST")
+                .path("/reset")
+                .json(&SubmitPasswordReset {
+                    token: token.clone(),
+                    password: String::from("new password"),
+                })
+        };
+        assert_eq!(reset().reply(&filter).await.status(), 200);
+        assert_eq!(reset().reply(&filter).await.status(), 422);
+        let connection = session.connection_pool.get().unwrap();
+        let updated = InternalUser::find_by_id(iuser.id, &connection).unwrap();
+        assert!(updated.verify_password("new password"));
+    }
+}
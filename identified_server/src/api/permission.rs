@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use warp::{Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::subscription::PermissionStreams,
+    api::helpers::tree::filters::main_filter as tree_filter,
+    database::models::permission::Permission, database::DatabaseConfig, utils::common::*,
+    utils::scope::Scope,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(tree_filter::<Permission>(
+            Scope::PermissionsRead,
+            Scope::PermissionsWrite,
+            db_config,
+            session,
+            permission_streams,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::models::session::test_token;
+    use crate::database::test_session;
+    use crate::utils::errors::handle_rejection;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn renaming_and_moving_take_the_subtree_along() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let token = {
+            let connection = session.connection_pool.get().unwrap();
+            let iuser = test_internal_user("permission@example.com", &connection);
+            test_token(iuser.id, db_config.clone(), &connection)
+        };
+        let filter = filters::main_filter(db_config, session, PermissionStreams::default())
+            .recover(handle_rejection);
+        let request = |method: &str, path: String, body: Value| {
+            warp::test::request()
+                .method(method)
+                .path(&path)
+                .header("authorization", &token)
+                .json(&body)
+        };
+        let create = |name: &str| request("POST", String::from("/"), json!({ "name": name }));
+        let response = create("docs").reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let docs: Permission = serde_json::from_slice(response.body()).unwrap();
+        let response = create("docs.read").reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let read: Permission = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(create("docs read").reply(&filter).await.status(), 422);
+
+        let name_of = |id: i64| {
+            let response = request("GET", format!("/{}", id), Value::Null);
+            let filter = filter.clone();
+            async move {
+                let response = response.reply(&filter).await;
+                serde_json::from_slice::<Permission>(response.body())
+                    .unwrap()
+                    .name
+            }
+        };
+        let rename = |name: &str| {
+            request(
+                "PATCH",
+                String::from("/"),
+                json!({ "id": docs.id, "name": name }),
+            )
+        };
+        assert_eq!(rename("files").reply(&filter).await.status(), 200);
+        assert_eq!(name_of(read.id).await, "files.read");
+        assert_eq!(rename("files.docs").reply(&filter).await.status(), 422);
+
+        let response = request(
+            "POST",
+            format!("/{}/move", read.id),
+            json!({ "parent_id": null }),
+        )
+        .reply(&filter)
+        .await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(name_of(read.id).await, "read");
+        assert_eq!(name_of(docs.id).await, "files");
+    }
+}
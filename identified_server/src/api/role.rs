@@ -0,0 +1,251 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::subscription::*,
+    api::helpers::tree::filters::main_filter as tree_filter,
+    database::models::namespace::Namespace,
+    database::models::role::Role,
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::scope::Scope,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            permissions_filter(db_config.clone(), session.clone())
+                .or(grant_permissions_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(revoke_permissions_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(tree_filter::<Role>(
+                    Scope::RolesRead,
+                    Scope::RolesWrite,
+                    db_config,
+                    session,
+                    permission_streams,
+                )),
+        )
+    }
+
+    pub fn permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(with_namespace(Scope::RolesRead, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::permissions)
+    }
+
+    pub fn grant_permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(
+                Scope::RolesWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::grant_permissions)
+    }
+
+    pub fn revoke_permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(warp::query::<IdsQuery>())
+            .and(with_namespace(
+                Scope::RolesWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::revoke_permissions)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+    use crate::database::models::role_permission::RolePermission;
+    use crate::database::models::user_role::UserRole;
+
+    pub async fn permissions(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = RolePermission::permissions_of(namespace.id, by_id, &connection)
+            .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn grant_permissions(
+        by_id: i64,
+        submitted: SubmitIds,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let affected = UserRole::inheriting_users(namespace.id, by_id, &connection)
+            .map_err(query_rejection)?;
+        let results = publishing(
+            namespace.id,
+            Some(&affected),
+            &permission_streams,
+            &connection,
+            || RolePermission::grant(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn revoke_permissions(
+        by_id: i64,
+        submitted: IdsQuery,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let affected = UserRole::inheriting_users(namespace.id, by_id, &connection)
+            .map_err(query_rejection)?;
+        let results = publishing(
+            namespace.id,
+            Some(&affected),
+            &permission_streams,
+            &connection,
+            || RolePermission::revoke(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::models::permission::Permission;
+    use crate::database::models::session::test_token;
+    use crate::database::models::tree::SubmitNode;
+    use crate::database::test_session;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn deleting_a_role_in_use_needs_cascade() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let (token, other_token, permission) = {
+            let connection = session.connection_pool.get().unwrap();
+            let iuser = test_internal_user("role-owner@example.com", &connection);
+            let other = test_internal_user("role-other@example.com", &connection);
+            let namespace_id = Namespace::of_member(iuser.id, &connection).unwrap()[0].id;
+            let submitted = SubmitNode {
+                name: String::from("docs"),
+            };
+            let permission =
+                Permission::create(namespace_id, iuser.id, submitted, &connection).unwrap();
+            (
+                test_token(iuser.id, db_config.clone(), &connection),
+                test_token(other.id, db_config.clone(), &connection),
+                permission,
+            )
+        };
+        let filter = filters::main_filter(db_config, session, PermissionStreams::default())
+            .recover(handle_rejection);
+        let create = |name: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/")
+                .header("authorization", &token)
+                .json(&json!({ "name": name }))
+        };
+        let response = create("eng").reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let eng: Role = serde_json::from_slice(response.body()).unwrap();
+        let response = create("eng.backend").reply(&filter).await;
+        assert_eq!(response.status(), 200);
+        let backend: Role = serde_json::from_slice(response.body()).unwrap();
+
+        let response = warp::test::request()
+            .method("POST")
+            .path(&format!("/{}/permissions", backend.id))
+            .header("authorization", &token)
+            .json(&json!({ "ids": [permission.id] }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request()
+            .path(&format!("/{}/permissions", backend.id))
+            .header("authorization", &token)
+            .reply(&filter)
+            .await;
+        let granted: Vec<Permission> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(granted.len(), 1);
+        assert_eq!(granted[0].id, permission.id);
+
+        // Other namespaces cannot see it, let alone delete it
+        let find = |token: &str, id: i64| {
+            warp::test::request()
+                .path(&format!("/{}", id))
+                .header("authorization", token)
+        };
+        assert_eq!(
+            find(&other_token, eng.id).reply(&filter).await.status(),
+            404
+        );
+
+        let delete = |query: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/{}{}", eng.id, query))
+                .header("authorization", &token)
+        };
+        assert_eq!(delete("").reply(&filter).await.status(), 409);
+        assert_eq!(find(&token, backend.id).reply(&filter).await.status(), 200);
+        assert_eq!(delete("?cascade=true").reply(&filter).await.status(), 200);
+        assert_eq!(find(&token, backend.id).reply(&filter).await.status(), 404);
+    }
+}
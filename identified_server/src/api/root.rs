@@ -1,35 +1,46 @@
+use diesel::pg::PgConnection;
 use futures::{FutureExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::convert::Infallible;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
 use tokio::sync::mpsc;
 use warp::{path::end, Filter, Rejection, Reply};
 
 use crate::{
+    api::api_key::filters::main_filter as api_key_filter,
+    api::email::filters::main_filter as email_filter,
     api::helpers::authorization::*,
+    api::helpers::subscription::*,
     api::internal::filters::main_filter as internal_filter,
+    api::lockout::{check_locked, filters::main_filter as lockout_filter},
+    api::me::filters::main_filter as me_filter,
+    api::namespace::filters::main_filter as namespace_filter,
+    api::password::filters::main_filter as password_filter,
+    api::permission::filters::main_filter as permission_filter,
+    api::role::filters::main_filter as role_filter,
+    api::session::filters::main_filter as session_filter,
+    api::setup::{filters::main_filter as setup_filter, SetupToken},
+    api::totp::filters::main_filter as totp_filter,
+    api::user::filters::main_filter as user_filter,
+    database::models::check::Decision,
     database::models::internal_user::InternalUser,
+    database::models::lockout::{account_subject, Lockout, LockoutPolicy, ACCOUNT, IP_ADDRESS},
+    database::models::namespace::Namespace,
+    database::models::permission::Permission,
+    database::models::session::{AuthSession, SessionOrigin},
+    database::models::totp::{create_challenge, verify_challenge, SubmitChallenge, Totp},
     database::{get_connection, DatabaseConfig},
+    mailer::Outbox,
     utils::common::*,
+    utils::errors::{handle_rejection, query_rejection},
+    utils::scope::Scope,
 };
 
 static CONNECTION_ID: AtomicUsize = AtomicUsize::new(1);
 
-type PermissionHashMap<T> = Arc<Mutex<HashMap<i64, HashMap<usize, T>>>>;
-type PermissionStreams =
-    PermissionHashMap<mpsc::UnboundedSender<Result<PermissionUpdate, warp::Error>>>;
-
-#[derive(Serialize)]
-pub struct PermissionUpdate {
-    pub user_id: i64,
-    pub permission_id: i64,
-    pub allowed: bool,
-}
-
 pub mod filters {
     use super::*;
 
@@ -37,48 +48,133 @@ pub mod filters {
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
         permission_streams: PermissionStreams,
-    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
-        let login = warp::path("login").and(login_filter(db_config.clone(), session.clone()));
+        setup_token: SetupToken,
+        outbox: Arc<Outbox>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+        let login = warp::path("login").and(
+            login_totp_filter(db_config.clone(), session.clone())
+                .or(login_filter(db_config.clone(), session.clone()))
+                .or(refresh_filter(db_config.clone(), session.clone())),
+        );
+        let setup = warp::path("setup").and(setup_filter(
+            db_config.clone(),
+            session.clone(),
+            setup_token,
+        ));
+        let password = warp::path("password").and(password_filter(
+            db_config.clone(),
+            session.clone(),
+            outbox.clone(),
+        ));
+        let email = warp::path("email").and(email_filter(
+            db_config.clone(),
+            session.clone(),
+            outbox.clone(),
+        ));
+        let me = warp::path("me").and(me_filter(db_config.clone(), session.clone(), outbox));
+        let logout = warp::path("logout").and(logout_filter(db_config.clone(), session.clone()));
         let internal = warp::path("internal")
-            .and(toss(with_authorization(true, session.clone())))
+            .and(toss(with_authorization(
+                Scope::InternalManage,
+                db_config.clone(),
+                session.clone(),
+            )))
             .and(internal_filter(db_config.clone(), session.clone()));
-        let check = warp::path("check")
-            .and(toss(with_authorization(false, session.clone())))
-            .and(check_filter(
+        let lockout = warp::path("lockout")
+            .and(toss(with_authorization(
+                Scope::InternalManage,
                 db_config.clone(),
                 session.clone(),
-                permission_streams.clone(),
-            ));
+            )))
+            .and(lockout_filter(session.clone()));
+        let namespace = warp::path("namespace").and(namespace_filter(
+            db_config.clone(),
+            session.clone(),
+            permission_streams.clone(),
+        ));
+        let user = warp::path("user").and(user_filter(
+            db_config.clone(),
+            session.clone(),
+            permission_streams.clone(),
+        ));
+        let role = warp::path("role").and(role_filter(
+            db_config.clone(),
+            session.clone(),
+            permission_streams.clone(),
+        ));
+        let permission = warp::path("permission").and(permission_filter(
+            db_config.clone(),
+            session.clone(),
+            permission_streams.clone(),
+        ));
+        let sessions =
+            warp::path("session").and(session_filter(db_config.clone(), session.clone()));
+        let api_keys = warp::path("apikey").and(api_key_filter(db_config.clone(), session.clone()));
+        let totp = warp::path("totp").and(totp_filter(db_config.clone(), session.clone()));
+        let check = warp::path("check").and(check_filter(db_config.clone(), session.clone()));
         let subscribe = warp::path("subscribe")
             .and(warp::ws())
-            .and(with_authorization(false, session.clone()))
-            .and(warp::body::json())
-            .and(warp::any().map(move || permission_streams.clone()))
+            .and(with_namespace(
+                Scope::PermissionsCheck,
+                db_config.clone(),
+                session.clone(),
+            ))
+            // The upgrade takes the body, so the permissions come in the query
+            .and(warp::query::<IdsQuery>())
+            .and(with(session.clone()))
+            .and(with_streams(permission_streams))
             .map(
-                |ws: warp::ws::Ws, iuser, requested_permissions, permission_streams| {
+                |ws: warp::ws::Ws,
+                 namespace,
+                 requested_permissions: IdsQuery,
+                 session,
+                 permission_streams| {
                     ws.on_upgrade(move |socket| {
                         handlers::new_subscription(
                             socket,
-                            requested_permissions,
-                            iuser,
+                            requested_permissions.ids,
+                            namespace,
+                            session,
                             permission_streams,
                         )
                     })
                 },
             );
-        warp::any().and(check.or(internal).or(login).or(subscribe))
+        warp::any()
+            .and(
+                check
+                    .or(internal)
+                    .or(lockout)
+                    .or(namespace)
+                    .or(user)
+                    .or(role)
+                    .or(permission)
+                    .or(login)
+                    .or(setup)
+                    .or(logout)
+                    .or(password)
+                    .or(email)
+                    .or(me)
+                    .or(sessions)
+                    .or(api_keys)
+                    .or(totp)
+                    .or(subscribe),
+            )
+            .recover(handle_rejection)
     }
 
     fn check_filter(
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
-        permission_streams: PermissionStreams,
     ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
         warp::any()
             .and(warp::get())
-            .and(with_db_config(db_config))
+            .and(with_namespace(
+                Scope::PermissionsCheck,
+                db_config,
+                session.clone(),
+            ))
             .and(with(session))
-            .and(with(permission_streams))
             .and(warp::body::json())
             .and(end())
             .and_then(handlers::check)
@@ -94,8 +190,50 @@ pub mod filters {
             .and(warp::post())
             .and(warp::body::json())
             .and(end())
+            .and(with_origin())
             .and_then(handlers::login)
     }
+
+    fn login_totp_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and(warp::post())
+            .and(warp::path("totp"))
+            .and(end())
+            .and(warp::body::json())
+            .and(with_origin())
+            .and_then(handlers::login_totp)
+    }
+
+    fn refresh_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("refresh"))
+            .and(end())
+            .and(with_auth_session(db_config.clone(), session.clone()))
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and_then(handlers::refresh)
+    }
+
+    fn logout_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(with_auth_session(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::logout)
+    }
 }
 
 mod handlers {
@@ -103,13 +241,6 @@ mod handlers {
     use crate::utils::errors::AuthenticationError::*;
     use warp::{filters::ws::Message, ws::WebSocket};
 
-    #[derive(Serialize, Deserialize)]
-    pub struct UserSubmission {
-        pub name: String,
-        pub email: String,
-        pub password: String,
-    }
-
     #[derive(Serialize, Deserialize)]
     pub struct LoginSubmission {
         pub email: String,
@@ -123,43 +254,189 @@ mod handlers {
     }
 
     pub async fn check(
-        db_config: Arc<DatabaseConfig>,
+        namespace: Namespace,
         session: Arc<Session>,
-        permission_streams: PermissionStreams,
         check: CheckRequest,
     ) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        // 1. Find permissions the internal user the user belongs to has
-        // 2. Find the parent permissions of the requested permission
-        // 3. Check if any of the user's role has any of those permissions
-        Ok(warp::reply::json(&0))
+        let decision = Decision::check(
+            namespace.id,
+            check.user_id,
+            check.permission_id,
+            &connection,
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&decision))
     }
 
+    fn login_subjects<'a>(
+        account: &'a str,
+        origin: &'a SessionOrigin,
+        policy: &LockoutPolicy,
+    ) -> Vec<(&'a str, &'a str)> {
+        let mut subjects = vec![(ACCOUNT, account)];
+        match origin.ip_address.as_deref() {
+            Some(ip_address) if policy.counts_ip_addresses() => {
+                subjects.push((IP_ADDRESS, ip_address))
+            }
+            _ => {}
+        }
+        subjects
+    }
+
+    fn start_session(
+        user: InternalUser,
+        origin: SessionOrigin,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<warp::reply::Json, Rejection> {
+        let (auth_session, token) = AuthSession::create(user.id, origin, db_config, connection)
+            .or(Err(warp::reject::custom(CouldNotGenerateAuthToken)))?;
+        Ok(warp::reply::json(&json!({
+                "authorization_token": token,
+                "expires_on": auth_session.expires_on,
+        })))
+    }
+
+    // Internal users with two-factor authentication get a challenge instead
+    // of a token, see `login_totp`.
     pub async fn login(
         db_config: Arc<DatabaseConfig>,
         session: Arc<Session>,
         login: LoginSubmission,
+        origin: SessionOrigin,
     ) -> Result<impl Reply, Rejection> {
         let connection = get_connection(session)?;
-        let user = InternalUser::find_by_email(login.email, &connection)
-            .or(Err(warp::reject::custom(InvalidEmail)))?;
-        let submitted_hashed = hash_password(login.password, user.salt, db_config.iterations);
-        let existing_hashed = user.password;
-        ring::constant_time::verify_slices_are_equal(&submitted_hashed, existing_hashed.as_slice())
-            .or(Err(warp::reject::custom(InvalidPassword)))?;
-        let iuser = InternalUser::update_auth_token(user.id, db_config, &connection)
+        let account = account_subject(&login.email);
+        let subjects = login_subjects(&account, &origin, &db_config.lockout);
+        check_locked(&subjects, &connection)?;
+        let user = InternalUser::find_by_email(login.email, &connection).ok();
+        let verified = match &user {
+            Some(user) => user.verify_password(&login.password),
+            // Hash anyway, so unknown emails take as long as wrong passwords
+            None => {
+                db_config.password_hasher.hash(&login.password);
+                false
+            }
+        };
+        let user = match (user, verified) {
+            (Some(user), true) => user,
+            _ => {
+                Lockout::record_failure(&subjects, &db_config.lockout, &connection)
+                    .map_err(query_rejection)?;
+                return Err(warp::reject::custom(InvalidCredentials));
+            }
+        };
+        // Upgrade hashes made with weaker parameters while the plain password
+        // is at hand. Failing to do so should not fail the login.
+        let user = match user.needs_rehash(&db_config) {
+            true => match InternalUser::rehash_password(
+                user.id,
+                login.password,
+                db_config.clone(),
+                &connection,
+            ) {
+                Ok(rehashed) => rehashed,
+                Err(e) => {
+                    log::error!("Could not rehash password (iuser_id = {}): {}", user.id, e);
+                    user
+                }
+            },
+            false => user,
+        };
+        // Failures stay counted until the code is entered too, otherwise
+        // knowing the password would allow guessing codes without limit.
+        if Totp::is_enabled(user.id, &connection).map_err(query_rejection)? {
+            let (challenge, expires_on) = create_challenge(&user, db_config);
+            return Ok(warp::reply::json(&json!({
+                    "challenge": challenge,
+                    "expires_on": expires_on,
+            })));
+        }
+        Lockout::clear(ACCOUNT, &account, &connection).map_err(query_rejection)?;
+        start_session(user, origin, db_config, &connection)
+    }
+
+    // The second step of logging in with two-factor authentication, trades
+    // the challenge and a code for a token.
+    pub async fn login_totp(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        submitted: SubmitChallenge,
+        origin: SessionOrigin,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let user = verify_challenge(&submitted.challenge, db_config.clone(), &connection)
+            .ok_or_else(|| warp::reject::custom(InvalidChallenge))?;
+        let account = account_subject(&user.email);
+        let subjects = login_subjects(&account, &origin, &db_config.lockout);
+        check_locked(&subjects, &connection)?;
+        let totp = Totp::find(user.id, &connection)
+            .map_err(query_rejection)?
+            .filter(|totp| totp.is_confirmed())
+            .ok_or_else(|| warp::reject::custom(InvalidChallenge))?;
+        if !totp
+            .verify(&submitted.code, &connection)
+            .map_err(query_rejection)?
+        {
+            Lockout::record_failure(&subjects, &db_config.lockout, &connection)
+                .map_err(query_rejection)?;
+            return Err(warp::reject::custom(InvalidCode));
+        }
+        Lockout::clear(ACCOUNT, &account, &connection).map_err(query_rejection)?;
+        start_session(user, origin, db_config, &connection)
+    }
+
+    // Rotates the token of the current session, sliding its expiry forward
+    // by another token lifetime. Other sessions are left alone.
+    pub async fn refresh(
+        _iuser: InternalUser,
+        auth_session: AuthSession,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let (auth_session, token) = AuthSession::refresh(auth_session.id, db_config, &connection)
             .or(Err(warp::reject::custom(CouldNotGenerateAuthToken)))?;
         Ok(warp::reply::json(&json!({
-                "authorization_token": iuser.auth_token
+                "authorization_token": token,
+                "expires_on": auth_session.expires_on,
         })))
     }
 
+    // Only ends the session the request was made with.
+    pub async fn logout(
+        iuser: InternalUser,
+        auth_session: AuthSession,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        AuthSession::revoke(iuser.id, auth_session.id, &connection).map_err(query_rejection)?;
+        Ok(http::StatusCode::OK)
+    }
+
     pub async fn new_subscription(
         ws: WebSocket,
         permission_ids: Vec<i64>,
-        iuser: InternalUser,
+        namespace: Namespace,
+        session: Arc<Session>,
         permission_streams: PermissionStreams,
     ) {
+        // Only permissions in the namespace subscribed through can be watched
+        let owned = get_connection(session).ok().and_then(|connection| {
+            Permission::find_by_ids(namespace.id, &permission_ids, &connection).ok()
+        });
+        let permission_ids: Vec<i64> = match owned {
+            Some(permissions) => permissions.into_iter().map(|p| p.id).collect(),
+            None => {
+                log::error!(
+                    "Could not load subscribed permissions (namespace_id = {})",
+                    namespace.id
+                );
+                return;
+            }
+        };
+
         // Create a new unbounded channel where we'll send the messages with
         // permission updates
         let (sender, receiver) = mpsc::unbounded_channel::<Result<PermissionUpdate, warp::Error>>();
@@ -177,7 +454,7 @@ mod handlers {
                 .forward(ws_sink)
                 .map(|result| {
                     if let Err(e) = result {
-                        eprintln!("Websocket sent error: {}", e);
+                        log::error!("Websocket sent error: {}", e);
                     }
                 }),
         );
@@ -191,9 +468,7 @@ mod handlers {
             let new_id = CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
             to_disconnect.insert(permission_id, new_id);
             let mut permission_streams = permission_streams.lock().unwrap();
-            let streams_for_permission = permission_streams
-                .entry(permission_id)
-                .or_insert(HashMap::new());
+            let streams_for_permission = permission_streams.entry(permission_id).or_default();
             streams_for_permission.insert(new_id, sender.clone());
         }
 
@@ -201,7 +476,7 @@ mod handlers {
             match result {
                 Ok(_) => {}
                 Err(e) => {
-                    eprintln!("Websocket error (iuser_id = {}): {}", iuser.id, e);
+                    log::warn!("Websocket error (namespace_id = {}): {}", namespace.id, e);
                     break;
                 }
             }
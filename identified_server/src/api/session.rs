@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    database::models::internal_user::InternalUser,
+    database::models::session::{AuthSession, PublicAuthSession},
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter(db_config.clone(), session.clone())
+                .or(revoke_filter(db_config.clone(), session.clone()))
+                .or(revoke_others_filter(db_config, session)),
+        )
+    }
+
+    pub fn all_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_auth_session(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::all)
+    }
+
+    pub fn revoke_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with_authentication(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::revoke)
+    }
+
+    pub fn revoke_others_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(end())
+            .and(with_auth_session(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::revoke_others)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Lists the live sessions of the caller, marking the one the request was
+    // made with.
+    pub async fn all(
+        iuser: InternalUser,
+        auth_session: AuthSession,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results: Vec<PublicAuthSession> = AuthSession::all(iuser.id, &connection)
+            .map_err(query_rejection)?
+            .into_iter()
+            .map(|s| PublicAuthSession::new(s, Some(auth_session.id)))
+            .collect();
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn revoke(
+        by_id: i64,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = AuthSession::revoke(iuser.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    // Logs out everywhere except for the current session.
+    pub async fn revoke_others(
+        iuser: InternalUser,
+        auth_session: AuthSession,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = AuthSession::revoke_all(iuser.id, Some(auth_session.id), &connection)
+            .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn auth_session(id: i64) -> AuthSession {
+        AuthSession {
+            id,
+            internal_user_id: 1,
+            token: String::from("secret-token-hash"),
+            created_on: Utc::now(),
+            last_seen: Utc::now(),
+            expires_on: Utc::now(),
+            user_agent: Some(String::from("curl/7.68.0")),
+            ip_address: Some(String::from("127.0.0.1")),
+        }
+    }
+
+    #[test]
+    fn public_session_has_no_token() {
+        let public = PublicAuthSession::new(auth_session(1), Some(1));
+        let serialized = serde_json::to_string(&public).unwrap();
+        assert!(!serialized.contains("token"), "{}", serialized);
+        assert!(public.current);
+        assert!(!PublicAuthSession::new(auth_session(2), Some(1)).current);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::public::reply_one,
+    database::models::internal_user::{InternalUser, SubmitInternalUser},
+    database::seed::DEFAULT_ROOT_PASSWORD,
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::scope::Scope,
+};
+
+// Only set while no admin exists and no root password was configured. It is
+// printed on startup and consumed by the first successful setup. An async
+// lock, as it is held across the database calls and hashing of the setup.
+pub type SetupToken = Arc<Mutex<Option<String>>>;
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitSetup {
+    pub token: String,
+    #[serde(flatten)]
+    pub admin: SubmitInternalUser,
+}
+
+// Compared in constant time, so the token cannot be guessed byte by byte.
+// Nothing matches once it has been used.
+fn token_matches(expected: Option<&str>, submitted: &str) -> bool {
+    expected.is_some_and(|expected| {
+        ring::constant_time::verify_slices_are_equal(expected.as_bytes(), submitted.as_bytes())
+            .is_ok()
+    })
+}
+
+pub fn with_setup_token(
+    setup_token: SetupToken,
+) -> impl Filter<Extract = (SetupToken,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || setup_token.clone())
+}
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        setup_token: SetupToken,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_db_config(db_config))
+            .and(with(session))
+            .and(with_setup_token(setup_token))
+            .and_then(handlers::setup)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    // Creates the first admin. The lock is held throughout, so two requests
+    // racing with the same token cannot both succeed. The password every
+    // fresh install knows is refused.
+    pub async fn setup(
+        submitted: SubmitSetup,
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        setup_token: SetupToken,
+    ) -> Result<impl Reply, Rejection> {
+        let mut setup_token = setup_token.lock().await;
+        if !token_matches(setup_token.as_deref(), &submitted.token) {
+            return Err(warp::reject::custom(AuthorizationError::InvalidToken));
+        }
+        if submitted.admin.password.is_empty() {
+            return Err(warp::reject::custom(InputError::new(
+                "password",
+                ValidationError::Required,
+            )));
+        }
+        if submitted.admin.password == DEFAULT_ROOT_PASSWORD {
+            return Err(warp::reject::custom(InputError::new(
+                "password",
+                ValidationError::Invalid,
+            )));
+        }
+        let connection = get_connection(session)?;
+        if InternalUser::admin_exists(&connection).map_err(query_rejection)? {
+            *setup_token = None;
+            return Err(warp::reject::custom(AuthorizationError::InvalidToken));
+        }
+        let result = InternalUser::create(submitted.admin, &Scope::ALL, db_config, &connection)
+            .map_err(query_rejection)?;
+        *setup_token = None;
+        Ok(reply_one(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_the_exact_token_matches() {
+        assert!(token_matches(Some("abcdef"), "abcdef"));
+        assert!(!token_matches(Some("abcdef"), "abcdeg"));
+        assert!(!token_matches(Some("abcdef"), "abc"));
+        assert!(!token_matches(Some("abcdef"), "abcdefg"));
+        assert!(!token_matches(Some("abcdef"), ""));
+        assert!(!token_matches(None, "abcdef"));
+        assert!(!token_matches(None, ""));
+    }
+
+    async fn status_of(setup_token: &SetupToken, token: &str, password: &str) -> u16 {
+        let filter = filters::main_filter(
+            Arc::new(DatabaseConfig::default()),
+            offline_session(),
+            setup_token.clone(),
+        )
+        .recover(handle_rejection);
+        warp::test::request()
+            .method("POST")
+            .json(&json!({
+                "token": token,
+                "name": "admin",
+                "email": "admin@example.com",
+                "password": password,
+            }))
+            .reply(&filter)
+            .await
+            .status()
+            .as_u16()
+    }
+
+    // Everything refused here is refused before the database is reached.
+    #[tokio::test]
+    async fn setup_needs_the_unused_token() {
+        let setup_token: SetupToken = Arc::new(Mutex::new(Some(String::from("abcdef"))));
+        assert_eq!(status_of(&setup_token, "wrong", "secret").await, 401);
+        assert_eq!(status_of(&setup_token, "abcdef", "").await, 422);
+        assert_eq!(
+            status_of(&setup_token, "abcdef", DEFAULT_ROOT_PASSWORD).await,
+            422
+        );
+        assert_eq!(
+            setup_token.lock().await.as_deref(),
+            Some("abcdef"),
+            "refused attempts keep the token"
+        );
+
+        *setup_token.lock().await = None;
+        assert_eq!(status_of(&setup_token, "abcdef", "secret").await, 401);
+    }
+}
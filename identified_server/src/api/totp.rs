@@ -0,0 +1,225 @@
+use diesel::pg::PgConnection;
+use serde_json::json;
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    database::models::internal_user::InternalUser,
+    database::models::totp::{SubmitCode, Totp},
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::totp::provisioning_uri,
+};
+
+// Shown by authenticator apps next to the email
+const ISSUER: &str = "identified";
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            status_filter(db_config.clone(), session.clone())
+                .or(enroll_filter(db_config.clone(), session.clone()))
+                .or(confirm_filter(db_config.clone(), session.clone()))
+                .or(recovery_filter(db_config.clone(), session.clone()))
+                .or(disable_filter(db_config, session)),
+        )
+    }
+
+    pub fn status_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::status)
+    }
+
+    pub fn enroll_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::enroll)
+    }
+
+    pub fn confirm_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("confirm"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::confirm)
+    }
+
+    pub fn recovery_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path("recovery"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::regenerate_recovery_codes)
+    }
+
+    pub fn disable_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_login(db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::disable)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    fn confirmed(iuser: &InternalUser, connection: &PgConnection) -> Result<Totp, Rejection> {
+        Totp::find(iuser.id, connection)
+            .map_err(query_rejection)?
+            .filter(|totp| totp.is_confirmed())
+            .ok_or_else(|| warp::reject::custom(InputError::new("totp", ValidationError::Required)))
+    }
+
+    fn check_code(totp: &Totp, code: &str, connection: &PgConnection) -> Result<(), Rejection> {
+        match totp.verify(code, connection).map_err(query_rejection)? {
+            true => Ok(()),
+            false => Err(warp::reject::custom(InputError::new(
+                "code",
+                ValidationError::Invalid,
+            ))),
+        }
+    }
+
+    pub async fn status(
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let enabled = Totp::is_enabled(iuser.id, &connection).map_err(query_rejection)?;
+        let recovery_codes_left =
+            Totp::recovery_codes_left(iuser.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&json!({
+            "enabled": enabled,
+            "required": iuser.totp_required,
+            "recovery_codes_left": recovery_codes_left,
+        })))
+    }
+
+    // Hands out a new secret, two-factor authentication is only enabled once
+    // a code generated from it is confirmed.
+    pub async fn enroll(
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        if Totp::is_enabled(iuser.id, &connection).map_err(query_rejection)? {
+            return Err(warp::reject::custom(InputError::new(
+                "totp",
+                ValidationError::AlreadyExists,
+            )));
+        }
+        let totp = Totp::enroll(iuser.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&json!({
+            "provisioning_uri": provisioning_uri(&totp.secret, ISSUER, &iuser.email),
+            "secret": totp.secret,
+        })))
+    }
+
+    // Enables two-factor authentication, the recovery codes are only shown in
+    // this response.
+    pub async fn confirm(
+        submitted: SubmitCode,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let totp = match Totp::find(iuser.id, &connection).map_err(query_rejection)? {
+            Some(totp) if !totp.is_confirmed() => totp,
+            Some(_) => {
+                return Err(warp::reject::custom(InputError::new(
+                    "totp",
+                    ValidationError::AlreadyExists,
+                )))
+            }
+            None => {
+                return Err(warp::reject::custom(InputError::new(
+                    "totp",
+                    ValidationError::Required,
+                )))
+            }
+        };
+        let recovery_codes = totp
+            .confirm(&submitted.code, &connection)
+            .map_err(query_rejection)?
+            .ok_or_else(|| {
+                warp::reject::custom(InputError::new("code", ValidationError::Invalid))
+            })?;
+        Ok(warp::reply::json(
+            &json!({ "recovery_codes": recovery_codes }),
+        ))
+    }
+
+    // Replaces the remaining recovery codes with a fresh set.
+    pub async fn regenerate_recovery_codes(
+        submitted: SubmitCode,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let totp = confirmed(&iuser, &connection)?;
+        check_code(&totp, &submitted.code, &connection)?;
+        let recovery_codes =
+            Totp::regenerate_recovery_codes(iuser.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(
+            &json!({ "recovery_codes": recovery_codes }),
+        ))
+    }
+
+    // Internal users that are required to use two-factor authentication
+    // cannot turn it off, an admin can reset it for them instead.
+    pub async fn disable(
+        submitted: SubmitCode,
+        iuser: InternalUser,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        if iuser.totp_required {
+            return Err(warp::reject::custom(AuthorizationError::Unauthorized));
+        }
+        let connection = get_connection(session)?;
+        let totp = confirmed(&iuser, &connection)?;
+        check_code(&totp, &submitted.code, &connection)?;
+        let results = Totp::disable(iuser.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
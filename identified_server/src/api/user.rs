@@ -0,0 +1,511 @@
+use std::sync::Arc;
+use warp::{path::end, Filter, Rejection, Reply};
+
+use crate::{
+    api::helpers::authorization::*,
+    api::helpers::subscription::*,
+    database::models::internal_user::InternalUser,
+    database::models::namespace::Namespace,
+    database::models::user::{SubmitUser, User},
+    database::{get_connection, DatabaseConfig},
+    utils::common::*,
+    utils::errors::*,
+    utils::scope::Scope,
+};
+
+pub mod filters {
+    use super::*;
+
+    pub fn main_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any().and(
+            all_filter(db_config.clone(), session.clone())
+                .or(find_filter(db_config.clone(), session.clone()))
+                .or(create_filter(db_config.clone(), session.clone()))
+                .or(update_filter(db_config.clone(), session.clone()))
+                .or(roles_filter(db_config.clone(), session.clone()))
+                .or(grant_roles_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(revoke_roles_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(permissions_filter(db_config.clone(), session.clone()))
+                .or(grant_permissions_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(revoke_permissions_filter(
+                    db_config.clone(),
+                    session.clone(),
+                    permission_streams.clone(),
+                ))
+                .or(delete_filter(db_config, session, permission_streams)),
+        )
+    }
+
+    pub fn all_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(end())
+            .and(with_namespace(Scope::UsersRead, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::all)
+    }
+
+    pub fn find_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with_namespace(Scope::UsersRead, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::find)
+    }
+
+    pub fn create_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_member(Scope::UsersWrite, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::create)
+    }
+
+    pub fn update_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::patch())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and_then(handlers::update)
+    }
+
+    pub fn delete_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(end())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::delete)
+    }
+
+    pub fn roles_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("roles"))
+            .and(end())
+            .and(with_namespace(Scope::UsersRead, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::roles)
+    }
+
+    pub fn grant_roles_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("roles"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::grant_roles)
+    }
+
+    pub fn revoke_roles_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("roles"))
+            .and(end())
+            .and(warp::query::<IdsQuery>())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::revoke_roles)
+    }
+
+    pub fn permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::get())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(with_namespace(Scope::UsersRead, db_config, session.clone()))
+            .and(with(session))
+            .and_then(handlers::permissions)
+    }
+
+    pub fn grant_permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::post())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(warp::body::content_length_limit(1024 * 16))
+            .and(warp::body::json())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::grant_permissions)
+    }
+
+    pub fn revoke_permissions_filter(
+        db_config: Arc<DatabaseConfig>,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        warp::any()
+            .and(warp::delete())
+            .and(warp::path::param::<i64>())
+            .and(warp::path("permissions"))
+            .and(end())
+            .and(warp::query::<IdsQuery>())
+            .and(with_namespace(
+                Scope::UsersWrite,
+                db_config,
+                session.clone(),
+            ))
+            .and(with(session))
+            .and(with_streams(permission_streams))
+            .and_then(handlers::revoke_permissions)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+    use crate::database::models::user::PatchUser;
+    use crate::database::models::user_permission::UserPermission;
+    use crate::database::models::user_role::UserRole;
+
+    pub async fn all(namespace: Namespace, session: Arc<Session>) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = User::all(namespace.id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn find(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result = User::find_by_id(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    pub async fn create(
+        submitted: SubmitUser,
+        iuser: InternalUser,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result = User::create(namespace.id, iuser.id, submitted, &connection)
+            .map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    pub async fn update(
+        by_id: i64,
+        submitted: PatchUser,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let result =
+            User::update(namespace.id, by_id, submitted, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&result))
+    }
+
+    pub async fn delete(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = publishing(
+            namespace.id,
+            Some(&[by_id]),
+            &permission_streams,
+            &connection,
+            || User::delete(namespace.id, by_id, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn roles(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results =
+            UserRole::roles_of(namespace.id, by_id, &connection).map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn grant_roles(
+        by_id: i64,
+        submitted: SubmitIds,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = publishing(
+            namespace.id,
+            Some(&[by_id]),
+            &permission_streams,
+            &connection,
+            || UserRole::grant(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn revoke_roles(
+        by_id: i64,
+        submitted: IdsQuery,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = publishing(
+            namespace.id,
+            Some(&[by_id]),
+            &permission_streams,
+            &connection,
+            || UserRole::revoke(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn permissions(
+        by_id: i64,
+        namespace: Namespace,
+        session: Arc<Session>,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = UserPermission::permissions_of(namespace.id, by_id, &connection)
+            .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn grant_permissions(
+        by_id: i64,
+        submitted: SubmitIds,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = publishing(
+            namespace.id,
+            Some(&[by_id]),
+            &permission_streams,
+            &connection,
+            || UserPermission::grant(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+
+    pub async fn revoke_permissions(
+        by_id: i64,
+        submitted: IdsQuery,
+        namespace: Namespace,
+        session: Arc<Session>,
+        permission_streams: PermissionStreams,
+    ) -> Result<impl Reply, Rejection> {
+        let connection = get_connection(session)?;
+        let results = publishing(
+            namespace.id,
+            Some(&[by_id]),
+            &permission_streams,
+            &connection,
+            || UserPermission::revoke(namespace.id, by_id, &submitted.ids, &connection),
+        )
+        .map_err(query_rejection)?;
+        Ok(warp::reply::json(&results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::models::session::test_token;
+    use crate::database::test_session;
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn users_belong_to_their_namespace_and_creator() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let (owner, token, other_token, namespace_id) = {
+            let connection = session.connection_pool.get().unwrap();
+            let owner = test_internal_user("user-owner@example.com", &connection);
+            let other = test_internal_user("user-other@example.com", &connection);
+            let namespace_id = Namespace::of_member(owner.id, &connection).unwrap()[0].id;
+            let token = test_token(owner.id, db_config.clone(), &connection);
+            let other_token = test_token(other.id, db_config.clone(), &connection);
+            (owner, token, other_token, namespace_id)
+        };
+        let filter = filters::main_filter(db_config, session, PermissionStreams::default())
+            .recover(handle_rejection);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/")
+            .header("authorization", &token)
+            .json(&json!({ "name": "alice" }))
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 200);
+        let created: User = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(created.owner_id, owner.id);
+        assert_eq!(created.namespace_id, namespace_id);
+
+        // Only the members of the namespace see it
+        let path = format!("/{}", created.id);
+        let find = |token: &str| {
+            warp::test::request()
+                .path(&path)
+                .header("authorization", token)
+        };
+        assert_eq!(find(&token).reply(&filter).await.status(), 200);
+        assert_eq!(find(&other_token).reply(&filter).await.status(), 404);
+        let response = find(&other_token)
+            .header(NAMESPACE_HEADER, namespace_id)
+            .reply(&filter)
+            .await;
+        assert_eq!(response.status(), 403);
+    }
+
+    #[tokio::test]
+    async fn patch_only_changes_the_given_fields() {
+        let session = match test_session() {
+            Some(session) => session,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let (token, user_id) = {
+            let connection = session.connection_pool.get().unwrap();
+            let iuser = test_internal_user("user-patch@example.com", &connection);
+            let namespace_id = Namespace::of_member(iuser.id, &connection).unwrap()[0].id;
+            let submitted = SubmitUser {
+                name: Some(String::from("alice")),
+            };
+            let user = User::create(namespace_id, iuser.id, submitted, &connection).unwrap();
+            (
+                test_token(iuser.id, db_config.clone(), &connection),
+                user.id,
+            )
+        };
+        let filter = filters::main_filter(db_config, session, PermissionStreams::default())
+            .recover(handle_rejection);
+        let patch = |body: Value| {
+            let filter = filter.clone();
+            let token = token.clone();
+            async move {
+                let response = warp::test::request()
+                    .method("PATCH")
+                    .path(&format!("/{}", user_id))
+                    .header("authorization", token)
+                    .json(&body)
+                    .reply(&filter)
+                    .await;
+                assert_eq!(response.status(), 200);
+                serde_json::from_slice::<User>(response.body())
+                    .unwrap()
+                    .name
+            }
+        };
+        assert_eq!(patch(json!({})).await.as_deref(), Some("alice"));
+        assert_eq!(
+            patch(json!({ "name": "bob" })).await.as_deref(),
+            Some("bob")
+        );
+        assert_eq!(patch(json!({ "name": null })).await, None);
+    }
+}
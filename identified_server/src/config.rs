@@ -0,0 +1,688 @@
+use chrono::Duration;
+use log::LevelFilter;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{env, fs};
+
+use crate::database::models::lockout::LockoutPolicy;
+use crate::database::seed::RootConfig;
+use crate::database::DatabaseConfig;
+use crate::utils::password::{Argon2idHasher, PasswordHasher, Pbkdf2Hasher};
+
+const DEFAULT_CONFIG_FILE: &str = "identified.toml";
+
+// Read from a TOML file, `identified.toml` unless `CONFIG_FILE` points
+// elsewhere, after which environment variables override single values.
+// Everything has a default, so a missing or empty file is fine.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: PoolConfig,
+    pub hashing: HashingConfig,
+    pub tokens: TokenConfig,
+    pub lockout: LockoutConfig,
+    pub mail: MailConfig,
+    pub logging: LoggingConfig,
+    pub root: RootConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    // Release builds are always treated as release, debug builds only when
+    // asked to. Outside of release mode listenfd is used when available.
+    pub release: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8000,
+            release: false,
+        }
+    }
+}
+
+// Serving over TLS requires both `cert_path` and `key_path`, the section is
+// read again on SIGHUP. Setting `client_ca_path` enables mutual TLS.
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert_path: Option<PathBuf>,
+    pub key_path: Option<PathBuf>,
+    pub client_ca_path: Option<PathBuf>,
+    // Otherwise clients without a certificate can still use bearer tokens
+    pub require_client_cert: bool,
+    // Certificate subjects, e.g. `CN=billing,O=Example`, mapped to the email
+    // of the internal user the client acts as.
+    pub client_users: HashMap<String, String>,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert_path.is_some() && self.key_path.is_some()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    // Falls back to `DATABASE_URL`
+    pub url: Option<String>,
+    pub max_size: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout_seconds: u64,
+    pub idle_timeout_seconds: Option<u64>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig {
+            url: None,
+            max_size: 10,
+            min_idle: None,
+            connection_timeout_seconds: 30,
+            idle_timeout_seconds: Some(600),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Argon2id,
+    Pbkdf2,
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HashAlgorithm, String> {
+        match s {
+            "argon2id" => Ok(HashAlgorithm::Argon2id),
+            "pbkdf2" => Ok(HashAlgorithm::Pbkdf2),
+            _ => Err(format!("unknown algorithm {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HashingConfig {
+    pub algorithm: HashAlgorithm,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub pbkdf2_iterations: u32,
+}
+
+impl Default for HashingConfig {
+    fn default() -> HashingConfig {
+        HashingConfig {
+            algorithm: HashAlgorithm::Argon2id,
+            argon2_memory_kib: argon2::Params::DEFAULT_M_COST,
+            argon2_iterations: argon2::Params::DEFAULT_T_COST,
+            argon2_parallelism: argon2::Params::DEFAULT_P_COST,
+            pbkdf2_iterations: 100_000,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenConfig {
+    pub lifetime_minutes: i64,
+    pub length: usize,
+    // Key for hashing stored tokens. Without it a random key is used and
+    // every session is lost on restart.
+    pub secret: Option<String>,
+    pub password_reset_lifetime_minutes: i64,
+    pub email_verification_lifetime_minutes: i64,
+}
+
+impl Default for TokenConfig {
+    fn default() -> TokenConfig {
+        TokenConfig {
+            lifetime_minutes: 120,
+            length: 30,
+            secret: None,
+            password_reset_lifetime_minutes: 60,
+            email_verification_lifetime_minutes: 24 * 60,
+        }
+    }
+}
+
+// Failed logins are counted per email and per IP address, unless
+// `max_ip_failures` is 0, see `LockoutPolicy`.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockoutConfig {
+    pub max_account_failures: i32,
+    pub max_ip_failures: i32,
+    pub base_delay_seconds: i64,
+    pub max_delay_seconds: i64,
+    pub lockout_minutes: i64,
+    pub reset_minutes: i64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> LockoutConfig {
+        LockoutConfig {
+            max_account_failures: 5,
+            max_ip_failures: 20,
+            base_delay_seconds: 1,
+            max_delay_seconds: 30,
+            lockout_minutes: 15,
+            reset_minutes: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MailTransport {
+    Log,
+    File,
+    Smtp,
+}
+
+impl FromStr for MailTransport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MailTransport, String> {
+        match s {
+            "log" => Ok(MailTransport::Log),
+            "file" => Ok(MailTransport::File),
+            "smtp" => Ok(MailTransport::Smtp),
+            _ => Err(format!("unknown transport {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    // Only for mail catchers on the same machine
+    None,
+    Starttls,
+    Tls,
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SmtpSecurity, String> {
+        match s {
+            "none" => Ok(SmtpSecurity::None),
+            "starttls" => Ok(SmtpSecurity::Starttls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            _ => Err(format!("unknown security {}", s)),
+        }
+    }
+}
+
+// How password reset and verification mails are sent, see `Outbox`. The
+// urls can contain `{token}`, otherwise mails only hold the token.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    pub password_reset_url: Option<String>,
+    pub email_verification_url: Option<String>,
+    // Where the `file` transport writes mails to
+    pub directory: Option<PathBuf>,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_security: SmtpSecurity,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> MailConfig {
+        MailConfig {
+            transport: MailTransport::Log,
+            from: String::from("identified@localhost"),
+            password_reset_url: None,
+            email_verification_url: None,
+            directory: None,
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_security: SmtpSecurity::Starttls,
+            smtp_username: None,
+            smtp_password: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // Same syntax as `RUST_LOG`, e.g. `info,identified_server=debug`
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: String::from("info"),
+        }
+    }
+}
+
+fn override_with<T: FromStr>(name: &str, target: &mut T) -> Result<(), String>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        *target = value
+            .parse()
+            .map_err(|e| format!("{} is invalid: {}", name, e))?;
+    }
+    Ok(())
+}
+
+fn override_option<T: FromStr>(name: &str, target: &mut Option<T>) -> Result<(), String>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Ok(value) = env::var(name) {
+        *target = Some(
+            value
+                .parse()
+                .map_err(|e| format!("{} is invalid: {}", name, e))?,
+        );
+    }
+    Ok(())
+}
+
+impl Config {
+    // Loads `.env`, the config file and the environment, in that order, and
+    // refuses anything that would only fail once the server is running.
+    pub fn load() -> Result<Config, String> {
+        dotenv::dotenv().ok();
+        let mut config = match env::var("CONFIG_FILE") {
+            Ok(path) => Config::from_file(&path)?,
+            Err(_) if fs::metadata(DEFAULT_CONFIG_FILE).is_ok() => {
+                Config::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            Err(_) => Config::default(),
+        };
+        config.apply_env()?;
+        config.server.release |= cfg!(not(debug_assertions));
+        config.root.read_password_file()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &str) -> Result<Config, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        toml::from_str(&contents).map_err(|e| format!("Could not parse {}: {}", path, e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), String> {
+        override_with("LISTEN_ADDRESS", &mut self.server.address)?;
+        override_with("PORT", &mut self.server.port)?;
+        if let Ok(profile) = env::var("PROFILE") {
+            self.server.release = profile == "release";
+        }
+
+        override_option("TLS_CERT_PATH", &mut self.tls.cert_path)?;
+        override_option("TLS_KEY_PATH", &mut self.tls.key_path)?;
+        override_option("TLS_CLIENT_CA_PATH", &mut self.tls.client_ca_path)?;
+        override_with("TLS_REQUIRE_CLIENT_CERT", &mut self.tls.require_client_cert)?;
+
+        override_option("DATABASE_URL", &mut self.database.url)?;
+        override_with("POOL_MAX_SIZE", &mut self.database.max_size)?;
+        override_option("POOL_MIN_IDLE", &mut self.database.min_idle)?;
+        override_with(
+            "POOL_CONNECTION_TIMEOUT_SECONDS",
+            &mut self.database.connection_timeout_seconds,
+        )?;
+        override_option(
+            "POOL_IDLE_TIMEOUT_SECONDS",
+            &mut self.database.idle_timeout_seconds,
+        )?;
+
+        override_with("PASSWORD_HASHER", &mut self.hashing.algorithm)?;
+        override_with("ARGON2_MEMORY_KIB", &mut self.hashing.argon2_memory_kib)?;
+        override_with("ARGON2_ITERATIONS", &mut self.hashing.argon2_iterations)?;
+        override_with("ARGON2_PARALLELISM", &mut self.hashing.argon2_parallelism)?;
+        override_with("PBKDF2_ITERATIONS", &mut self.hashing.pbkdf2_iterations)?;
+
+        override_with("TOKEN_LIFETIME_MINUTES", &mut self.tokens.lifetime_minutes)?;
+        override_with("TOKEN_LENGTH", &mut self.tokens.length)?;
+        override_option("TOKEN_SECRET", &mut self.tokens.secret)?;
+        override_with(
+            "PASSWORD_RESET_LIFETIME_MINUTES",
+            &mut self.tokens.password_reset_lifetime_minutes,
+        )?;
+        override_with(
+            "EMAIL_VERIFICATION_LIFETIME_MINUTES",
+            &mut self.tokens.email_verification_lifetime_minutes,
+        )?;
+
+        override_with(
+            "LOCKOUT_MAX_ACCOUNT_FAILURES",
+            &mut self.lockout.max_account_failures,
+        )?;
+        override_with("LOCKOUT_MAX_IP_FAILURES", &mut self.lockout.max_ip_failures)?;
+        override_with(
+            "LOCKOUT_BASE_DELAY_SECONDS",
+            &mut self.lockout.base_delay_seconds,
+        )?;
+        override_with(
+            "LOCKOUT_MAX_DELAY_SECONDS",
+            &mut self.lockout.max_delay_seconds,
+        )?;
+        override_with("LOCKOUT_MINUTES", &mut self.lockout.lockout_minutes)?;
+        override_with("LOCKOUT_RESET_MINUTES", &mut self.lockout.reset_minutes)?;
+
+        override_with("MAIL_TRANSPORT", &mut self.mail.transport)?;
+        override_with("MAIL_FROM", &mut self.mail.from)?;
+        override_option("MAIL_PASSWORD_RESET_URL", &mut self.mail.password_reset_url)?;
+        override_option(
+            "MAIL_EMAIL_VERIFICATION_URL",
+            &mut self.mail.email_verification_url,
+        )?;
+        override_option("MAIL_DIRECTORY", &mut self.mail.directory)?;
+        override_option("SMTP_HOST", &mut self.mail.smtp_host)?;
+        override_with("SMTP_PORT", &mut self.mail.smtp_port)?;
+        override_with("SMTP_SECURITY", &mut self.mail.smtp_security)?;
+        override_option("SMTP_USERNAME", &mut self.mail.smtp_username)?;
+        override_option("SMTP_PASSWORD", &mut self.mail.smtp_password)?;
+
+        override_with("LOG_LEVEL", &mut self.logging.level)?;
+
+        override_with("ROOT_NAME", &mut self.root.name)?;
+        override_with("ROOT_EMAIL", &mut self.root.email)?;
+        override_option("ROOT_PASSWORD", &mut self.root.password)?;
+        override_option("ROOT_PASSWORD_FILE", &mut self.root.password_file)?;
+        Ok(())
+    }
+
+    // Reports every problem at once instead of one per restart.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            problems.push(String::from(
+                "tls.cert_path and tls.key_path must be set together",
+            ));
+        }
+        if self.tls.client_ca_path.is_some() && !self.tls.is_enabled() {
+            problems.push(String::from(
+                "tls.client_ca_path requires tls.cert_path and tls.key_path",
+            ));
+        }
+        if self.tls.client_ca_path.is_none()
+            && (self.tls.require_client_cert || !self.tls.client_users.is_empty())
+        {
+            problems.push(String::from(
+                "tls.require_client_cert and tls.client_users require tls.client_ca_path",
+            ));
+        }
+        if self.database.url.is_none() {
+            problems.push(String::from("database.url or DATABASE_URL must be set"));
+        }
+        if self.database.max_size == 0 {
+            problems.push(String::from("database.max_size must be at least 1"));
+        }
+        if self.database.min_idle.unwrap_or(0) > self.database.max_size {
+            problems.push(String::from(
+                "database.min_idle cannot exceed database.max_size",
+            ));
+        }
+        if self.database.connection_timeout_seconds == 0 {
+            problems.push(String::from(
+                "database.connection_timeout_seconds must be at least 1",
+            ));
+        }
+        if let Err(e) = self.password_hasher() {
+            problems.push(e);
+        }
+        if self.tokens.lifetime_minutes <= 0 {
+            problems.push(String::from("tokens.lifetime_minutes must be positive"));
+        }
+        if self.tokens.length < 16 {
+            problems.push(String::from("tokens.length must be at least 16"));
+        }
+        if self.tokens.secret.as_deref() == Some("") {
+            problems.push(String::from("tokens.secret cannot be empty"));
+        }
+        if self.tokens.password_reset_lifetime_minutes <= 0
+            || self.tokens.email_verification_lifetime_minutes <= 0
+        {
+            problems.push(String::from(
+                "tokens.password_reset_lifetime_minutes and tokens.email_verification_lifetime_minutes must be positive",
+            ));
+        }
+        let lockout = &self.lockout;
+        if lockout.max_account_failures < 1 {
+            problems.push(String::from(
+                "lockout.max_account_failures must be at least 1",
+            ));
+        }
+        if lockout.max_ip_failures < 0 {
+            problems.push(String::from(
+                "lockout.max_ip_failures must be at least 0, which turns it off",
+            ));
+        }
+        if lockout.base_delay_seconds < 0 || lockout.max_delay_seconds < lockout.base_delay_seconds
+        {
+            problems.push(String::from(
+                "lockout.max_delay_seconds must be at least lockout.base_delay_seconds",
+            ));
+        }
+        if lockout.lockout_minutes < 1 || lockout.reset_minutes < 1 {
+            problems.push(String::from(
+                "lockout.lockout_minutes and lockout.reset_minutes must be at least 1",
+            ));
+        }
+        let mail = &self.mail;
+        if !mail.from.contains('@') {
+            problems.push(String::from("mail.from must be an email"));
+        }
+        // The log transport writes reset and verification tokens to the log
+        if mail.transport == MailTransport::Log && self.server.release {
+            problems.push(String::from(
+                "mail.transport cannot be log in release mode, use file or smtp",
+            ));
+        }
+        if mail.transport == MailTransport::File
+            && !mail.directory.as_ref().is_some_and(|d| d.is_dir())
+        {
+            problems.push(String::from(
+                "mail.directory must be an existing directory for the file transport",
+            ));
+        }
+        if mail.transport == MailTransport::Smtp && mail.smtp_host.is_none() {
+            problems.push(String::from(
+                "mail.smtp_host must be set for the smtp transport",
+            ));
+        }
+        if mail.smtp_username.is_some() != mail.smtp_password.is_some() {
+            problems.push(String::from(
+                "mail.smtp_username and mail.smtp_password must be set together",
+            ));
+        }
+        for directive in self.logging.level.split(',') {
+            let level = directive.rsplit('=').next().unwrap_or_default();
+            if LevelFilter::from_str(level).is_err() {
+                problems.push(format!("logging.level has an invalid level `{}`", level));
+            }
+        }
+        if let Err(e) = self.root.validate(self.server.release) {
+            problems.push(e);
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems.join("\n")),
+        }
+    }
+
+    pub fn listen_address(&self) -> SocketAddr {
+        SocketAddr::new(self.server.address, self.server.port)
+    }
+
+    pub fn password_hasher(&self) -> Result<Box<dyn PasswordHasher>, String> {
+        let hashing = &self.hashing;
+        match hashing.algorithm {
+            HashAlgorithm::Argon2id => Argon2idHasher::new(
+                hashing.argon2_memory_kib,
+                hashing.argon2_iterations,
+                hashing.argon2_parallelism,
+            )
+            .map(|hasher| Box::new(hasher) as Box<dyn PasswordHasher>)
+            .map_err(|e| format!("hashing has invalid argon2 parameters: {}", e)),
+            HashAlgorithm::Pbkdf2 => NonZeroU32::new(hashing.pbkdf2_iterations)
+                .map(|iterations| Box::new(Pbkdf2Hasher { iterations }) as Box<dyn PasswordHasher>)
+                .ok_or_else(|| String::from("hashing.pbkdf2_iterations must be at least 1")),
+        }
+    }
+
+    pub fn database_config(&self) -> Result<DatabaseConfig, String> {
+        let token_key = match &self.tokens.secret {
+            Some(secret) => hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+            None => {
+                log::warn!("tokens.secret is not set, sessions will not survive a restart");
+                hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                    .map_err(|_| String::from("Could not generate a token key"))?
+            }
+        };
+        Ok(DatabaseConfig {
+            password_hasher: self.password_hasher()?,
+            api_key_length: self.tokens.length,
+            token_lifetime: Duration::minutes(self.tokens.lifetime_minutes),
+            token_key,
+            password_reset_lifetime: Duration::minutes(self.tokens.password_reset_lifetime_minutes),
+            email_verification_lifetime: Duration::minutes(
+                self.tokens.email_verification_lifetime_minutes,
+            ),
+            lockout: LockoutPolicy {
+                max_account_failures: self.lockout.max_account_failures,
+                max_ip_failures: self.lockout.max_ip_failures,
+                base_delay: Duration::seconds(self.lockout.base_delay_seconds),
+                max_delay: Duration::seconds(self.lockout.max_delay_seconds),
+                lockout: Duration::minutes(self.lockout.lockout_minutes),
+                reset: Duration::minutes(self.lockout.reset_minutes),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::common::hash_token;
+
+    fn with_database(mut config: Config) -> Config {
+        config.database.url = Some(String::from("postgres://localhost/identified"));
+        config
+    }
+
+    #[test]
+    fn token_secret_keeps_hashes_across_restarts() {
+        let hash = |config: &Config| {
+            let db_config = config.database_config().unwrap();
+            hash_token(&db_config.token_key, "token")
+        };
+        let mut config = Config {
+            tokens: TokenConfig {
+                secret: Some(String::from("secret")),
+                ..TokenConfig::default()
+            },
+            ..Config::default()
+        };
+        assert_eq!(hash(&config), hash(&config));
+        // Without a secret every start generates a new key
+        config.tokens.secret = None;
+        assert_ne!(hash(&config), hash(&config));
+    }
+
+    #[test]
+    fn empty_file_uses_defaults() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.listen_address().port(), 8000);
+        assert_eq!(config.hashing.algorithm, HashAlgorithm::Argon2id);
+        assert!(with_database(config).validate().is_ok());
+    }
+
+    #[test]
+    fn file_sections_are_read() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            address = "0.0.0.0"
+            port = 9000
+
+            [database]
+            url = "postgres://db/identified"
+            max_size = 4
+
+            [hashing]
+            algorithm = "pbkdf2"
+            pbkdf2_iterations = 1000
+
+            [tokens]
+            lifetime_minutes = 15
+
+            [logging]
+            level = "warn,identified_server=debug"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.listen_address().to_string(), "0.0.0.0:9000");
+        assert_eq!(config.database.max_size, 4);
+        assert_eq!(config.hashing.algorithm, HashAlgorithm::Pbkdf2);
+        assert_eq!(config.tokens.lifetime_minutes, 15);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn example_file_is_valid() {
+        let config: Config = toml::from_str(include_str!("../identified.toml.example")).unwrap();
+        assert!(with_database(config).validate().is_ok());
+    }
+
+    #[test]
+    fn release_refuses_logging_mail() {
+        let mut config = with_database(Config::default());
+        config.server.release = true;
+        assert!(config.validate().unwrap_err().contains("mail.transport"));
+        config.mail.transport = MailTransport::Smtp;
+        config.mail.smtp_host = Some(String::from("smtp.example.com"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        assert!(toml::from_str::<Config>("[server]\nprot = 8000").is_err());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = with_database(Config::default());
+        config.database.max_size = 0;
+        config.tokens.lifetime_minutes = 0;
+        config.hashing.argon2_iterations = 0;
+        config.logging.level = String::from("loud");
+        config.tls.cert_path = Some(PathBuf::from("cert.pem"));
+        config.lockout.max_delay_seconds = 0;
+        config.mail.transport = MailTransport::Smtp;
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.lines().count(), 7, "{}", problems);
+    }
+}
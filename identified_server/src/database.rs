@@ -1,37 +1,51 @@
+pub mod functions;
 pub mod models;
+// Every generated table imports diesel_ltree, most do not use it
+#[allow(unused_imports)]
 pub mod schema;
 pub mod seed;
 
+use chrono::Duration;
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool, PoolError, PooledConnection};
-use dotenv::dotenv;
+use ring::hmac;
 use ring::rand::SystemRandom;
 use std::sync::Arc;
-use std::{env, num};
+use std::time;
 use warp::{reject, Rejection};
 
+use crate::config::PoolConfig;
+use crate::database::models::lockout::LockoutPolicy;
 use crate::utils::common::Session;
 use crate::utils::errors::DbError::DatabaseConnectionError;
+use crate::utils::password::{Argon2idHasher, PasswordHasher};
 
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
-// TODO: Make it harder to shoot yourself in the foot by using different number
-// of hashing iterations when passwords have already been defined
 pub struct DatabaseConfig {
-    pub iterations: num::NonZeroU32,
-    pub rng: SystemRandom,
-    pub salt_length: usize,
+    // Only applies to newly hashed passwords. Existing hashes keep the scheme
+    // and parameters they were created with and are upgraded on the next login.
+    pub password_hasher: Box<dyn PasswordHasher>,
     pub api_key_length: usize,
+    pub token_lifetime: Duration,
+    // Key used to hash auth tokens before they are stored
+    pub token_key: hmac::Key,
+    pub password_reset_lifetime: Duration,
+    pub email_verification_lifetime: Duration,
+    pub lockout: LockoutPolicy,
 }
 
-impl DatabaseConfig {
-    pub fn default() -> DatabaseConfig {
+impl Default for DatabaseConfig {
+    fn default() -> DatabaseConfig {
         DatabaseConfig {
-            iterations: num::NonZeroU32::new(100).unwrap(),
-            rng: SystemRandom::new(),
-            salt_length: 12,
+            password_hasher: Box::new(Argon2idHasher::default()),
+            token_key: hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()).unwrap(),
             api_key_length: 12,
+            token_lifetime: Duration::minutes(120),
+            password_reset_lifetime: Duration::minutes(60),
+            email_verification_lifetime: Duration::minutes(24 * 60),
+            lockout: LockoutPolicy::default(),
         }
     }
 }
@@ -39,21 +53,71 @@ impl DatabaseConfig {
 pub fn get_connection(session: Arc<Session>) -> Result<PgPooledConnection, Rejection> {
     match session.connection_pool.get() {
         Ok(connection) => Ok(connection),
-        Err(err) => {
-            return Err(reject::custom(DatabaseConnectionError(format!("{}", err))));
-        }
+        Err(err) => Err(reject::custom(DatabaseConnectionError(format!("{}", err)))),
     }
 }
 
-fn init_pool(database_url: &str) -> Result<PgPool, PoolError> {
+fn init_pool(database_url: &str, pool_config: &PoolConfig) -> Result<PgPool, PoolError> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
-    Pool::builder().build(manager)
+    Pool::builder()
+        .max_size(pool_config.max_size)
+        .min_idle(pool_config.min_idle)
+        .connection_timeout(time::Duration::from_secs(
+            pool_config.connection_timeout_seconds,
+        ))
+        .idle_timeout(
+            pool_config
+                .idle_timeout_seconds
+                .map(time::Duration::from_secs),
+        )
+        .build(manager)
+}
+
+// `Config::validate` guarantees the url is set. It is left out of the error,
+// it usually carries the database password.
+pub fn establish_connection(pool_config: &PoolConfig) -> PgPool {
+    let database_url = pool_config
+        .url
+        .as_deref()
+        .expect("DATABASE_URL must be set");
+    init_pool(database_url, pool_config)
+        .unwrap_or_else(|e| panic!("Error connecting to the database: {}", e))
+}
+
+// A connection to the `DATABASE_URL_TEST` database `make test` sets up, in a
+// transaction that is never committed. `None` when it is not set, so tests
+// needing the database can skip themselves.
+#[cfg(test)]
+pub fn test_connection() -> Option<PgConnection> {
+    use diesel::Connection;
+    let database_url = std::env::var("DATABASE_URL_TEST").ok()?;
+    let connection = PgConnection::establish(&database_url)
+        .unwrap_or_else(|e| panic!("Error connecting to DATABASE_URL_TEST: {}", e));
+    connection.begin_test_transaction().unwrap();
+    Some(connection)
 }
 
-pub fn establish_connection() -> PgPool {
-    // Load .env into the environment variables
-    dotenv().ok();
+// Like `test_connection`, for handlers that check connections out of the
+// session. The pool holds that one connection.
+#[cfg(test)]
+pub fn test_session() -> Option<Arc<Session>> {
+    #[derive(Debug)]
+    struct NeverCommit;
+
+    impl diesel::r2d2::CustomizeConnection<PgConnection, diesel::r2d2::Error> for NeverCommit {
+        fn on_acquire(&self, connection: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+            use diesel::Connection;
+            connection
+                .begin_test_transaction()
+                .map_err(diesel::r2d2::Error::QueryError)
+        }
+    }
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    init_pool(&database_url).expect("Error connecting to {}")
+    let database_url = std::env::var("DATABASE_URL_TEST").ok()?;
+    let connection_pool = Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(NeverCommit))
+        .build(ConnectionManager::new(database_url))
+        .unwrap_or_else(|e| panic!("Error connecting to DATABASE_URL_TEST: {}", e));
+    Some(Arc::new(Session { connection_pool }))
 }
@@ -0,0 +1,6 @@
+use diesel::sql_types::Text;
+use diesel_ltree::Ltree;
+
+// Declared here rather than imported from diesel_ltree, which keeps the
+// helper types needed to name a selection private.
+sql_function!(fn ltree2text(ltree: Ltree) -> Text);
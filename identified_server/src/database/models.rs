@@ -1,44 +1,21 @@
-use diesel::prelude::*;
-use diesel_ltree::Ltree;
-pub mod internal_user;
-
-#[derive(Queryable)]
-pub struct Namespace {
-    pub id: i64,
-    pub name: String,
-    pub iuserid: i64,
-}
-
-#[derive(Queryable)]
-pub struct User {
-    pub id: i64,
-    pub name: String,
-}
-
-#[derive(Queryable)]
-pub struct UserRoles {
-    pub id: i64,
-    pub user_id: i64,
-    pub role_id: Role,
-}
+// Declare `ltree_tree!` and `assignment!`, which have to come before the
+// models using them
+#[macro_use]
+pub mod tree;
+#[macro_use]
+mod assignment;
 
-#[derive(Queryable)]
-pub struct Role {
-    pub id: i64,
-    pub parent_role: Option<i64>,
-    pub name: String,
-}
-
-pub struct RolePermission {
-    pub id: i64,
-    pub role_id: i64,
-    pub permission_id: Role,
-}
-
-#[derive(Queryable)]
-pub struct Permission {
-    pub id: i64,
-    pub name: String,
-    pub parent_id: i64,
-    pub permission: Ltree,
-}
+pub mod api_key;
+pub mod check;
+pub mod email_token;
+pub mod internal_user;
+pub mod lockout;
+pub mod namespace;
+pub mod permission;
+pub mod role;
+pub mod role_permission;
+pub mod session;
+pub mod totp;
+pub mod user;
+pub mod user_permission;
+pub mod user_role;
@@ -0,0 +1,193 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::database::models::internal_user::InternalUser;
+use crate::database::schema::{api_key, internal_user};
+use crate::database::DatabaseConfig;
+use crate::utils::common::{hash_token, random_string};
+use crate::utils::scope::{self, Scope};
+
+// Every API key starts with this, which is how they are told apart from
+// session tokens.
+pub const API_KEY_PREFIX: &str = "idk_";
+
+// Long-lived credential for services, acting on behalf of the internal user
+// that created it. Like sessions, only the keyed hash of the key is stored.
+#[derive(Queryable)]
+pub struct ApiKey {
+    pub id: i64,
+    pub internal_user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub key: String,
+    pub scopes: Vec<String>,
+    // Empty allows every namespace the internal user is a member of
+    pub namespace_ids: Vec<i64>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_key"]
+pub struct CreateApiKey {
+    pub internal_user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub key: String,
+    pub scopes: Vec<String>,
+    pub namespace_ids: Vec<i64>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PublicApiKey {
+    pub id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    pub namespace_ids: Vec<i64>,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl From<ApiKey> for PublicApiKey {
+    fn from(api_key: ApiKey) -> PublicApiKey {
+        PublicApiKey {
+            id: api_key.id,
+            name: api_key.name,
+            prefix: api_key.prefix,
+            scopes: scope::from_strings(&api_key.scopes),
+            namespace_ids: api_key.namespace_ids,
+            created_on: api_key.created_on,
+            expires_on: api_key.expires_on,
+            last_used: api_key.last_used,
+        }
+    }
+}
+
+// Only returned when the key is created, the key cannot be recovered later.
+#[derive(Serialize, Deserialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: PublicApiKey,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub namespace_ids: Vec<i64>,
+    // Never expires when left out
+    pub expires_on: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub fn all(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<ApiKey>, diesel::result::Error> {
+        api_key::table
+            .filter(api_key::internal_user_id.eq(by_internal_user_id))
+            .order(api_key::created_on.desc())
+            .load(connection)
+    }
+
+    // Returns the raw key next to the row, it cannot be recovered later.
+    pub fn create(
+        by_internal_user_id: i64,
+        new: SubmitApiKey,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(ApiKey, String), diesel::result::Error> {
+        let new_key = format!(
+            "{}{}",
+            API_KEY_PREFIX,
+            random_string(db_config.api_key_length)
+        );
+        let mut namespace_ids = new.namespace_ids;
+        namespace_ids.sort_unstable();
+        namespace_ids.dedup();
+        let api_key = diesel::insert_into(api_key::table)
+            .values(CreateApiKey {
+                internal_user_id: by_internal_user_id,
+                name: new.name,
+                prefix: new_key.chars().take(API_KEY_PREFIX.len() + 4).collect(),
+                key: hash_token(&db_config.token_key, &new_key),
+                scopes: scope::to_strings(&new.scopes),
+                namespace_ids,
+                created_on: Utc::now(),
+                expires_on: new.expires_on,
+            })
+            .get_result(connection)?;
+        Ok((api_key, new_key))
+    }
+
+    pub fn find_by_key(
+        by_key: String,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(ApiKey, InternalUser), diesel::result::Error> {
+        api_key::table
+            .inner_join(internal_user::table)
+            .filter(api_key::key.eq(hash_token(&db_config.token_key, &by_key)))
+            .first(connection)
+    }
+
+    pub fn touch(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(api_key::table.find(by_id))
+            .set(api_key::last_used.eq(Utc::now()))
+            .execute(connection)
+    }
+
+    // Only deletes the key if it belongs to `by_internal_user_id`.
+    pub fn revoke(
+        by_internal_user_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        let deleted = diesel::delete(
+            api_key::table
+                .filter(api_key::internal_user_id.eq(by_internal_user_id))
+                .filter(api_key::id.eq(by_id)),
+        )
+        .execute(connection)?;
+        match deleted {
+            0 => Err(diesel::result::Error::NotFound),
+            n => Ok(n),
+        }
+    }
+
+    pub fn revoke_all(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(api_key::table.filter(api_key::internal_user_id.eq(by_internal_user_id)))
+            .execute(connection)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_on
+            .is_some_and(|expires_on| expires_on <= Utc::now())
+    }
+
+    pub fn allows_every_namespace(&self) -> bool {
+        self.namespace_ids.is_empty()
+    }
+
+    pub fn allows_namespace(&self, namespace_id: i64) -> bool {
+        self.namespace_ids.is_empty() || self.namespace_ids.contains(&namespace_id)
+    }
+
+    // The key can do at most what the internal user behind it can, even when
+    // the internal user loses scopes after the key was created.
+    pub fn restrict(&self, iuser: &mut InternalUser) {
+        iuser.scopes.retain(|s| self.scopes.contains(s));
+    }
+}
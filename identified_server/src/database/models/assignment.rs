@@ -0,0 +1,79 @@
+// Implements listing, granting and revoking for `$model`, which assigns
+// `$target` nodes to a `$holder`. `$table` has a unique `($holder_id,
+// $target_id)` pair and rows are inserted through `$create`. Both the holder
+// and every target have to belong to the namespace, and `$list` returns the
+// targets the holder ends up with.
+macro_rules! assignment {
+    (
+        $model:ident,
+        $create:ident,
+        $table:ident,
+        $holder:ident . $holder_id:ident,
+        $target:ident . $target_id:ident,
+        $list:ident
+    ) => {
+        impl $model {
+            pub fn $list(
+                by_namespace_id: i64,
+                by_holder_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<$target>, diesel::result::Error> {
+                $holder::find_by_id(by_namespace_id, by_holder_id, connection)?;
+                let target_ids: Vec<i64> = $table::table
+                    .filter($table::$holder_id.eq(by_holder_id))
+                    .select($table::$target_id)
+                    .load(connection)?;
+                $target::find_by_ids(by_namespace_id, &target_ids, connection)
+            }
+
+            // Targets the holder already has are left untouched.
+            pub fn grant(
+                by_namespace_id: i64,
+                by_holder_id: i64,
+                by_target_ids: &[i64],
+                connection: &PgConnection,
+            ) -> Result<Vec<$target>, diesel::result::Error> {
+                let target_ids = $crate::utils::common::distinct(by_target_ids);
+                connection.transaction(|| {
+                    $holder::find_by_id(by_namespace_id, by_holder_id, connection)?;
+                    if $target::find_by_ids(by_namespace_id, &target_ids, connection)?.len()
+                        != target_ids.len()
+                    {
+                        return Err(diesel::result::Error::NotFound);
+                    }
+                    let new: Vec<$create> = target_ids
+                        .iter()
+                        .map(|&target_id| $create {
+                            $holder_id: by_holder_id,
+                            $target_id: target_id,
+                        })
+                        .collect();
+                    diesel::insert_into($table::table)
+                        .values(new)
+                        .on_conflict(($table::$holder_id, $table::$target_id))
+                        .do_nothing()
+                        .execute(connection)?;
+                    $model::$list(by_namespace_id, by_holder_id, connection)
+                })
+            }
+
+            pub fn revoke(
+                by_namespace_id: i64,
+                by_holder_id: i64,
+                by_target_ids: &[i64],
+                connection: &PgConnection,
+            ) -> Result<Vec<$target>, diesel::result::Error> {
+                connection.transaction(|| {
+                    $holder::find_by_id(by_namespace_id, by_holder_id, connection)?;
+                    diesel::delete(
+                        $table::table
+                            .filter($table::$holder_id.eq(by_holder_id))
+                            .filter($table::$target_id.eq_any(by_target_ids)),
+                    )
+                    .execute(connection)?;
+                    $model::$list(by_namespace_id, by_holder_id, connection)
+                })
+            }
+        }
+    };
+}
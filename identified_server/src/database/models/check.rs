@@ -0,0 +1,379 @@
+use diesel::prelude::*;
+use diesel::sql_types::{Array, BigInt, Bool, Nullable};
+use diesel_ltree::{Ltree, LtreeExtensions};
+use serde::Serialize;
+
+use crate::database::functions::ltree2text;
+use crate::database::schema::{
+    permission, role, role_permission, user, user_permission, user_role,
+};
+use crate::utils::common::{is_ltree_descendant, ltree_ancestors, ltree_depth};
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MatchedVia {
+    Direct { permission_id: i64 },
+    Role { role_id: i64, permission_id: i64 },
+}
+
+#[derive(Serialize, Debug)]
+pub struct Decision {
+    pub allowed: bool,
+    pub matched_via: Option<MatchedVia>,
+}
+
+// A permission the user holds directly, as its ltree path.
+#[derive(Queryable, Debug)]
+pub struct DirectGrant {
+    pub permission_id: i64,
+    pub permission: String,
+}
+
+// A permission held by a role, both as ltree paths.
+#[derive(Queryable, Debug)]
+pub struct RoleGrant {
+    pub role_id: i64,
+    pub role: String,
+    pub permission_id: i64,
+    pub permission: String,
+}
+
+// Whether a user is allowed a permission, without saying why.
+#[derive(QueryableByName, Debug)]
+pub struct Allowed {
+    #[sql_type = "BigInt"]
+    pub user_id: i64,
+    #[sql_type = "BigInt"]
+    pub permission_id: i64,
+    #[sql_type = "Bool"]
+    pub allowed: bool,
+}
+
+impl Decision {
+    fn from_match(matched_via: Option<MatchedVia>) -> Decision {
+        Decision {
+            allowed: matched_via.is_some(),
+            matched_via,
+        }
+    }
+
+    // Decides on `requested` given the roles of the user and the grants that
+    // might cover it. A direct grant wins over one through a role, and among
+    // either the most specific grant is reported. Roles inherit the grants of
+    // their ancestors in the role tree.
+    pub fn resolve(
+        requested: &str,
+        user_roles: &[String],
+        direct: &[DirectGrant],
+        via_roles: &[RoleGrant],
+    ) -> Decision {
+        let direct = direct
+            .iter()
+            .filter(|g| is_ltree_descendant(requested, &g.permission))
+            .min_by_key(|g| (-ltree_depth(&g.permission), g.permission_id));
+        if let Some(grant) = direct {
+            return Decision::from_match(Some(MatchedVia::Direct {
+                permission_id: grant.permission_id,
+            }));
+        }
+        let via_role = via_roles
+            .iter()
+            .filter(|g| is_ltree_descendant(requested, &g.permission))
+            .filter(|g| user_roles.iter().any(|r| is_ltree_descendant(r, &g.role)))
+            .min_by_key(|g| (-ltree_depth(&g.permission), g.role_id, g.permission_id));
+        Decision::from_match(via_role.map(|grant| MatchedVia::Role {
+            role_id: grant.role_id,
+            permission_id: grant.permission_id,
+        }))
+    }
+
+    // A permission is granted if the user holds it, or any of its ancestors
+    // in the ltree hierarchy, either directly or through one of their roles
+    // or the roles those inherit from.
+    // Both the user and the permission have to belong to `by_namespace_id`.
+    pub fn check(
+        by_namespace_id: i64,
+        by_user_id: i64,
+        by_permission_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Decision, diesel::result::Error> {
+        user::table
+            .find(by_user_id)
+            .filter(user::namespace_id.eq(by_namespace_id))
+            .select(user::id)
+            .first::<i64>(connection)?;
+        let requested = permission::table
+            .find(by_permission_id)
+            .filter(permission::namespace_id.eq(by_namespace_id))
+            .select(ltree2text(permission::name))
+            .first::<String>(connection)?;
+
+        let direct: Vec<DirectGrant> = user_permission::table
+            .inner_join(permission::table)
+            .filter(user_permission::user_id.eq(by_user_id))
+            .filter(permission::namespace_id.eq(by_namespace_id))
+            .filter(permission::name.contains(Ltree(requested.clone())))
+            .select((permission::id, ltree2text(permission::name)))
+            .load(connection)?;
+        let user_roles: Vec<String> = user_role::table
+            .inner_join(role::table)
+            .filter(user_role::user_id.eq(by_user_id))
+            .filter(role::namespace_id.eq(by_namespace_id))
+            .select(ltree2text(role::name))
+            .load(connection)?;
+        // Only the grants of the user's roles and their ancestors can apply
+        let inherited: Vec<String> = user_roles.iter().flat_map(|r| ltree_ancestors(r)).collect();
+        let via_roles: Vec<RoleGrant> = role_permission::table
+            .inner_join(role::table)
+            .inner_join(permission::table)
+            .filter(role::namespace_id.eq(by_namespace_id))
+            .filter(ltree2text(role::name).eq_any(inherited))
+            .filter(permission::namespace_id.eq(by_namespace_id))
+            .filter(permission::name.contains(Ltree(requested.clone())))
+            .select((
+                role::id,
+                ltree2text(role::name),
+                permission::id,
+                ltree2text(permission::name),
+            ))
+            .load(connection)?;
+        Ok(Decision::resolve(
+            &requested,
+            &user_roles,
+            &direct,
+            &via_roles,
+        ))
+    }
+
+    // Decides like `check` on every pair of `user_ids`, or every user of the
+    // namespace without them, and `permission_ids` in a single query. The
+    // `allowed_agrees_with_check` test keeps the two rules in line.
+    pub fn allowed(
+        by_namespace_id: i64,
+        user_ids: Option<&[i64]>,
+        permission_ids: &[i64],
+        connection: &PgConnection,
+    ) -> Result<Vec<Allowed>, diesel::result::Error> {
+        diesel::sql_query(
+            r#"
+            SELECT u.id AS user_id, p.id AS permission_id,
+                EXISTS (
+                    SELECT 1 FROM user_permission up
+                    JOIN permission g ON g.id = up.permission_id
+                    WHERE up.user_id = u.id AND g.namespace_id = $1 AND g.name @> p.name
+                ) OR EXISTS (
+                    SELECT 1 FROM user_role ur
+                    JOIN role r ON r.id = ur.role_id AND r.namespace_id = $1
+                    JOIN role a ON a.namespace_id = $1 AND a.name @> r.name
+                    JOIN role_permission rp ON rp.role_id = a.id
+                    JOIN permission g ON g.id = rp.permission_id AND g.namespace_id = $1
+                    WHERE ur.user_id = u.id AND g.name @> p.name
+                ) AS allowed
+            FROM "user" u CROSS JOIN permission p
+            WHERE u.namespace_id = $1 AND p.namespace_id = $1
+                AND ($2 IS NULL OR u.id = ANY($2)) AND p.id = ANY($3)
+            "#,
+        )
+        .bind::<BigInt, _>(by_namespace_id)
+        .bind::<Nullable<Array<BigInt>>, _>(user_ids.map(<[i64]>::to_vec))
+        .bind::<Array<BigInt>, _>(permission_ids.to_vec())
+        .load(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::models::namespace::Namespace;
+    use crate::database::models::permission::Permission;
+    use crate::database::models::role::Role;
+    use crate::database::models::role_permission::RolePermission;
+    use crate::database::models::tree::SubmitNode;
+    use crate::database::models::user::{SubmitUser, User};
+    use crate::database::models::user_permission::UserPermission;
+    use crate::database::models::user_role::UserRole;
+    use crate::database::test_connection;
+
+    fn direct(permission_id: i64, permission: &str) -> DirectGrant {
+        DirectGrant {
+            permission_id,
+            permission: permission.to_string(),
+        }
+    }
+
+    fn via_role(role_id: i64, role: &str, permission_id: i64, permission: &str) -> RoleGrant {
+        RoleGrant {
+            role_id,
+            role: role.to_string(),
+            permission_id,
+            permission: permission.to_string(),
+        }
+    }
+
+    fn roles(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn nothing_granted_is_denied() {
+        let decision = Decision::resolve("billing.read", &roles(&["eng"]), &[], &[]);
+        assert!(!decision.allowed);
+        assert_eq!(decision.matched_via, None);
+    }
+
+    #[test]
+    fn direct_grant_beats_role_grant() {
+        let decision = Decision::resolve(
+            "billing.read",
+            &roles(&["eng"]),
+            &[direct(1, "billing")],
+            &[via_role(7, "eng", 2, "billing.read")],
+        );
+        assert!(decision.allowed);
+        assert_eq!(
+            decision.matched_via,
+            Some(MatchedVia::Direct { permission_id: 1 })
+        );
+    }
+
+    #[test]
+    fn most_specific_prefix_wins() {
+        let decision = Decision::resolve(
+            "billing.invoices.read",
+            &[],
+            &[
+                direct(1, "billing"),
+                direct(2, "billing.invoices"),
+                direct(3, "billing.invoices.read.own"),
+                direct(4, "billing.invoicesx"),
+            ],
+            &[],
+        );
+        assert_eq!(
+            decision.matched_via,
+            Some(MatchedVia::Direct { permission_id: 2 })
+        );
+
+        let decision = Decision::resolve(
+            "billing.invoices.read",
+            &roles(&["eng"]),
+            &[],
+            &[
+                via_role(7, "eng", 1, "billing"),
+                via_role(7, "eng", 2, "billing.invoices"),
+            ],
+        );
+        assert_eq!(
+            decision.matched_via,
+            Some(MatchedVia::Role {
+                role_id: 7,
+                permission_id: 2
+            })
+        );
+    }
+
+    #[test]
+    fn ancestor_role_is_inherited() {
+        let grants = [via_role(7, "eng", 1, "billing")];
+        let decision = Decision::resolve("billing.read", &roles(&["eng.backend"]), &[], &grants);
+        assert_eq!(
+            decision.matched_via,
+            Some(MatchedVia::Role {
+                role_id: 7,
+                permission_id: 1
+            })
+        );
+
+        // Neither descendants nor siblings pass their grants on
+        let grants = [
+            via_role(8, "eng.backend", 1, "billing"),
+            via_role(9, "eng.frontend", 1, "billing"),
+        ];
+        let decision = Decision::resolve("billing.read", &roles(&["eng"]), &[], &grants);
+        assert!(!decision.allowed);
+        let decision = Decision::resolve("billing.read", &roles(&["eng.backend"]), &[], &grants);
+        assert_eq!(
+            decision.matched_via,
+            Some(MatchedVia::Role {
+                role_id: 8,
+                permission_id: 1
+            })
+        );
+    }
+
+    // Subscriptions publish what `allowed` decides, `/check` answers what
+    // `check` decides, so both have to agree on every pair.
+    #[test]
+    fn allowed_agrees_with_check() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let iuser = test_internal_user("check@example.com", &connection);
+        let ns = Namespace::of_member(iuser.id, &connection).unwrap()[0].id;
+        let node = |path: &str| SubmitNode {
+            name: path.to_string(),
+        };
+        let permission = |path: &str| {
+            Permission::create(ns, iuser.id, node(path), &connection)
+                .unwrap()
+                .id
+        };
+        let role = |path: &str| {
+            Role::create(ns, iuser.id, node(path), &connection)
+                .unwrap()
+                .id
+        };
+        let user = |roles: &[i64], permissions: &[i64]| {
+            let id = User::create(ns, iuser.id, SubmitUser { name: None }, &connection)
+                .unwrap()
+                .id;
+            UserRole::grant(ns, id, roles, &connection).unwrap();
+            UserPermission::grant(ns, id, permissions, &connection).unwrap();
+            id
+        };
+
+        let billing = permission("billing");
+        let invoices = permission("billing.invoices");
+        let read = permission("billing.invoices.read");
+        let lookalike = permission("billing.invoicesx");
+        let hr = permission("hr");
+        let payroll = permission("hr.payroll");
+        let permissions = [billing, invoices, read, lookalike, hr, payroll];
+        let eng = role("eng");
+        let backend = role("eng.backend");
+        let frontend = role("eng.frontend");
+        RolePermission::grant(ns, eng, &[invoices], &connection).unwrap();
+        RolePermission::grant(ns, frontend, &[hr], &connection).unwrap();
+
+        let nobody = user(&[], &[]);
+        let direct = user(&[], &[billing]);
+        let inherits = user(&[backend], &[]);
+        let own_role = user(&[eng], &[]);
+        let both = user(&[frontend], &[payroll]);
+        let users = [nobody, direct, inherits, own_role, both];
+
+        let allowed = Decision::allowed(ns, None, &permissions, &connection).unwrap();
+        assert_eq!(allowed.len(), users.len() * permissions.len());
+        for pair in &allowed {
+            let checked =
+                Decision::check(ns, pair.user_id, pair.permission_id, &connection).unwrap();
+            assert_eq!(pair.allowed, checked.allowed, "{:?}", pair);
+        }
+        let is_allowed = |user_id: i64, permission_id: i64| {
+            allowed
+                .iter()
+                .any(|a| a.user_id == user_id && a.permission_id == permission_id && a.allowed)
+        };
+        assert!(!permissions.iter().any(|&p| is_allowed(nobody, p)));
+        assert!(is_allowed(direct, read) && is_allowed(direct, lookalike));
+        assert!(is_allowed(inherits, read) && !is_allowed(inherits, lookalike));
+        assert!(!is_allowed(own_role, billing) && !is_allowed(own_role, hr));
+        assert!(is_allowed(both, payroll) && is_allowed(both, invoices));
+
+        let some = Decision::allowed(ns, Some(&[both]), &[read, hr], &connection).unwrap();
+        assert_eq!(some.len(), 2);
+        assert!(some.iter().all(|a| a.user_id == both && a.allowed));
+    }
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::database::models::internal_user::InternalUser;
+use crate::database::schema::{email_token, internal_user};
+use crate::database::DatabaseConfig;
+use crate::utils::common::{hash_token, random_string};
+
+pub const PASSWORD_RESET: &str = "password_reset";
+pub const EMAIL_VERIFICATION: &str = "email_verification";
+
+// Sent by email to prove the internal user can read mail sent to `email`.
+// Like sessions only the keyed hash of the token is stored, and a token is
+// deleted as soon as it is used.
+#[derive(Queryable)]
+pub struct EmailToken {
+    pub id: i64,
+    pub internal_user_id: i64,
+    // `password_reset` or `email_verification`
+    pub purpose: String,
+    pub email: String,
+    pub token: String,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_token"]
+pub struct CreateEmailToken<'a> {
+    pub internal_user_id: i64,
+    pub purpose: &'a str,
+    pub email: &'a str,
+    pub token: String,
+    pub created_on: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitEmail {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitToken {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitPasswordReset {
+    pub token: String,
+    pub password: String,
+}
+
+impl EmailToken {
+    // Replaces any earlier token for the same purpose, only the latest email
+    // works. Returns the raw token, it cannot be recovered later.
+    pub fn create(
+        iuser: &InternalUser,
+        purpose: &str,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(EmailToken, String), diesel::result::Error> {
+        let new_token = random_string(db_config.api_key_length);
+        let now = Utc::now();
+        let lifetime = match purpose {
+            PASSWORD_RESET => db_config.password_reset_lifetime,
+            _ => db_config.email_verification_lifetime,
+        };
+        connection.transaction(|| {
+            diesel::delete(
+                email_token::table
+                    .filter(email_token::internal_user_id.eq(iuser.id))
+                    .filter(email_token::purpose.eq(purpose)),
+            )
+            .execute(connection)?;
+            let email_token = diesel::insert_into(email_token::table)
+                .values(CreateEmailToken {
+                    internal_user_id: iuser.id,
+                    purpose,
+                    email: &iuser.email,
+                    token: hash_token(&db_config.token_key, &new_token),
+                    created_on: now,
+                    expires_on: now + lifetime,
+                })
+                .get_result(connection)?;
+            Ok((email_token, new_token))
+        })
+    }
+
+    // Deletes the token and returns its internal user, unless the token is
+    // unknown, expired, meant for something else or sent to an email the
+    // internal user no longer has.
+    pub fn consume(
+        by_token: &str,
+        purpose: &str,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<Option<InternalUser>, diesel::result::Error> {
+        let hashed = hash_token(&db_config.token_key, by_token);
+        connection.transaction(|| {
+            let found: Option<(EmailToken, InternalUser)> = email_token::table
+                .inner_join(internal_user::table)
+                .filter(email_token::token.eq(hashed))
+                .for_update()
+                .first(connection)
+                .optional()?;
+            let (email_token, iuser) = match found {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            diesel::delete(email_token::table.find(email_token.id)).execute(connection)?;
+            Ok(match email_token.is_valid_for(purpose, &iuser) {
+                true => Some(iuser),
+                false => None,
+            })
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_on <= Utc::now()
+    }
+
+    // Whether the token proves `iuser` reads mail sent to their current email.
+    fn is_valid_for(&self, purpose: &str, iuser: &InternalUser) -> bool {
+        self.purpose == purpose
+            && self.internal_user_id == iuser.id
+            && self.email == iuser.email
+            && !self.is_expired()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::internal_user::test_internal_user;
+    use crate::database::test_connection;
+    use chrono::Duration;
+
+    fn email_token(iuser: &InternalUser) -> EmailToken {
+        EmailToken {
+            id: 1,
+            internal_user_id: iuser.id,
+            purpose: String::from(PASSWORD_RESET),
+            email: iuser.email.clone(),
+            token: String::new(),
+            created_on: Utc::now(),
+            expires_on: Utc::now() + Duration::minutes(5),
+        }
+    }
+
+    fn internal_user() -> InternalUser {
+        InternalUser {
+            id: 1,
+            name: String::from("root"),
+            email: String::from("root@admin.com"),
+            password: String::new(),
+            created_on: Utc::now(),
+            last_login: None,
+            scopes: Vec::new(),
+            totp_required: false,
+            email_verified_on: None,
+        }
+    }
+
+    #[test]
+    fn token_only_works_as_sent() {
+        let iuser = internal_user();
+        assert!(email_token(&iuser).is_valid_for(PASSWORD_RESET, &iuser));
+        assert!(!email_token(&iuser).is_valid_for(EMAIL_VERIFICATION, &iuser));
+        let expired = EmailToken {
+            expires_on: Utc::now() - Duration::seconds(1),
+            ..email_token(&iuser)
+        };
+        assert!(!expired.is_valid_for(PASSWORD_RESET, &iuser));
+        let moved = InternalUser {
+            email: String::from("moved@admin.com"),
+            ..internal_user()
+        };
+        assert!(!email_token(&iuser).is_valid_for(PASSWORD_RESET, &moved));
+        let other = InternalUser {
+            id: 2,
+            ..internal_user()
+        };
+        assert!(!email_token(&iuser).is_valid_for(PASSWORD_RESET, &other));
+    }
+
+    #[test]
+    fn token_is_single_use_and_replaced_by_the_next() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let db_config = Arc::new(DatabaseConfig::default());
+        let iuser = test_internal_user("email-token@example.com", &connection);
+        let consume = |token: &str, purpose: &str| {
+            EmailToken::consume(token, purpose, db_config.clone(), &connection)
+                .unwrap()
+                .map(|iuser| iuser.id)
+        };
+
+        let (_, token) =
+            EmailToken::create(&iuser, PASSWORD_RESET, db_config.clone(), &connection).unwrap();
+        assert_eq!(consume(&token, PASSWORD_RESET), Some(iuser.id));
+        assert_eq!(consume(&token, PASSWORD_RESET), None);
+
+        let (_, first) =
+            EmailToken::create(&iuser, PASSWORD_RESET, db_config.clone(), &connection).unwrap();
+        let (_, second) =
+            EmailToken::create(&iuser, PASSWORD_RESET, db_config.clone(), &connection).unwrap();
+        assert_eq!(consume(&first, PASSWORD_RESET), None);
+        assert_eq!(consume(&second, EMAIL_VERIFICATION), None);
+        // Used up by the wrong purpose as well
+        assert_eq!(consume(&second, PASSWORD_RESET), None);
+
+        let (_, token) =
+            EmailToken::create(&iuser, EMAIL_VERIFICATION, db_config.clone(), &connection).unwrap();
+        InternalUser::update_profile(
+            iuser.id,
+            None,
+            Some(String::from("email-token-moved@example.com")),
+            &connection,
+        )
+        .unwrap();
+        assert_eq!(consume(&token, EMAIL_VERIFICATION), None);
+    }
+}
@@ -1,28 +1,36 @@
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use warp::*;
 
+use crate::database::models::namespace::{CreateNamespaceMember, Namespace, SubmitNamespace};
+use crate::database::models::session::AuthSession;
 use crate::database::models::*;
 use crate::database::schema::internal_user::*;
+use crate::database::schema::{namespace, namespace_member, permission, role, user};
 use crate::database::DatabaseConfig;
-use crate::utils::common::{hash_password, random_string};
-use crate::utils::errors::*;
+use crate::utils::common::to_hex;
+use crate::utils::password::{needs_rehash, verify_password};
+use crate::utils::scope::{self, Scope};
 
-#[derive(Queryable, Serialize, Deserialize)]
+// Holds credentials, so deliberately not `Serialize`. Responses go through
+// `PublicInternalUser` instead.
+#[derive(Queryable, Clone)]
 pub struct InternalUser {
     pub id: i64,
     pub name: String,
     pub email: String,
-    #[serde(with = "serde_bytes")]
-    pub password: Vec<u8>,
-    pub salt: String,
+    // PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+    pub password: String,
     pub created_on: DateTime<Utc>,
     pub last_login: Option<DateTime<Utc>>,
-    pub auth_token: Option<String>,
-    pub expires_on: Option<DateTime<Utc>>,
-    pub admin: bool,
+    // See `Scope`, kept as text so unknown scopes do not break loading
+    pub scopes: Vec<String>,
+    // Set by an admin, see `Totp`
+    pub totp_required: bool,
+    // Cleared whenever the email changes, see `EmailToken`
+    pub email_verified_on: Option<DateTime<Utc>>,
 }
 
 #[derive(Insertable)]
@@ -30,20 +38,72 @@ pub struct InternalUser {
 pub struct CreateInternalUser {
     pub name: String,
     pub email: String,
-    pub password: Vec<u8>,
-    pub salt: String,
+    pub password: String,
     pub created_on: DateTime<Utc>,
-    pub admin: bool,
+    pub scopes: Vec<String>,
 }
 
-#[derive(AsChangeset, Identifiable)]
+// Fields left as `None` are not touched.
+#[derive(AsChangeset)]
 #[table_name = "internal_user"]
 pub struct UpdateInternalUser {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
+impl UpdateInternalUser {
+    fn is_empty(&self) -> bool {
+        self.name.is_none() && self.email.is_none() && self.password.is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PublicInternalUser {
     pub id: i64,
     pub name: String,
     pub email: String,
-    pub password: Vec<u8>,
-    pub salt: String,
+    pub created_on: DateTime<Utc>,
+    pub last_login: Option<DateTime<Utc>>,
+    pub scopes: Vec<Scope>,
+    pub totp_required: bool,
+    pub email_verified_on: Option<DateTime<Utc>>,
+}
+
+impl From<InternalUser> for PublicInternalUser {
+    fn from(iuser: InternalUser) -> PublicInternalUser {
+        PublicInternalUser {
+            id: iuser.id,
+            name: iuser.name,
+            email: iuser.email,
+            created_on: iuser.created_on,
+            last_login: iuser.last_login,
+            scopes: scope::from_strings(&iuser.scopes),
+            totp_required: iuser.totp_required,
+            email_verified_on: iuser.email_verified_on,
+        }
+    }
+}
+
+impl PublicInternalUser {
+    // Strong entity tag of the representation, compared against `If-Match`
+    // to catch concurrent edits.
+    pub fn etag(&self) -> String {
+        let serialized = serde_json::to_vec(self).unwrap_or_default();
+        let digest = digest::digest(&digest::SHA256, &serialized);
+        format!("\"{}\"", to_hex(&digest.as_ref()[..16]))
+    }
+}
+
+// What came of an internal user deleting themselves.
+#[derive(Debug, PartialEq)]
+pub enum SelfDeletion {
+    Deleted(usize),
+    // Nobody else would be able to manage internal users
+    LastAdmin,
+    // A namespace nobody else is a member of is not empty and `cascade` was
+    // not given
+    NamespaceInUse,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -53,30 +113,81 @@ pub struct SubmitInternalUser {
     pub password: String,
 }
 
+// Creating an internal user optionally grants scopes, `Scope::DEFAULT`
+// otherwise.
+#[derive(Serialize, Deserialize)]
+pub struct SubmitNewInternalUser {
+    #[serde(flatten)]
+    pub iuser: SubmitInternalUser,
+    pub scopes: Option<Vec<Scope>>,
+}
+
+// Left out fields stay as they are.
+#[derive(Serialize, Deserialize)]
+pub struct PatchInternalUser {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
+// Left out fields stay as they are. Changing the email needs the current
+// password.
+#[derive(Serialize, Deserialize)]
+pub struct SubmitProfile {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitPasswordChange {
+    pub current_password: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitPassword {
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitScopes {
+    pub scopes: Vec<Scope>,
+}
+
 // TODO: Swap Error with a custom error type
 impl InternalUser {
     pub fn all(connection: &PgConnection) -> Result<Vec<InternalUser>, diesel::result::Error> {
         dsl::internal_user.load(connection)
     }
 
+    // Every internal user starts out with a namespace of their own, named
+    // after their email like the ones created when namespaces were added.
     pub fn create(
         new: SubmitInternalUser,
-        is_admin: bool,
+        with_scopes: &[Scope],
         db_config: Arc<DatabaseConfig>,
         connection: &PgConnection,
     ) -> Result<InternalUser, diesel::result::Error> {
-        let user_salt = random_string(db_config.salt_length);
-        let hashed = hash_password(new.password, user_salt.clone(), db_config.iterations);
-        diesel::insert_into(internal_user::table)
-            .values(vec![CreateInternalUser {
-                name: new.name,
-                email: new.email,
-                password: hashed.to_vec(),
-                salt: user_salt,
-                created_on: Utc::now(),
-                admin: is_admin,
-            }])
-            .get_result::<InternalUser>(connection)
+        connection.transaction(|| {
+            let result = diesel::insert_into(internal_user::table)
+                .values(CreateInternalUser {
+                    name: new.name,
+                    email: new.email,
+                    password: db_config.password_hasher.hash(&new.password),
+                    created_on: Utc::now(),
+                    scopes: scope::to_strings(with_scopes),
+                })
+                .get_result::<InternalUser>(connection)?;
+            Namespace::create(
+                result.id,
+                SubmitNamespace {
+                    name: result.email.clone(),
+                },
+                connection,
+            )?;
+            Ok(result)
+        })
     }
 
     pub fn find_by_id(
@@ -95,54 +206,518 @@ impl InternalUser {
             .first(connection)
     }
 
-    pub fn find_by_auth_token(
-        by_auth_token: String,
+    pub fn has_scope(&self, required: Scope) -> bool {
+        self.scopes.iter().any(|s| s == required.as_str())
+    }
+
+    // Whether anyone is able to manage internal users.
+    pub fn admin_exists(connection: &PgConnection) -> Result<bool, diesel::result::Error> {
+        diesel::select(diesel::dsl::exists(
+            dsl::internal_user.filter(scopes.contains(vec![Scope::InternalManage.to_string()])),
+        ))
+        .get_result(connection)
+    }
+
+    // Replaces every scope the internal user holds. Sessions stay valid, the
+    // scopes are checked on each request.
+    pub fn set_scopes(
+        by_id: i64,
+        new: &[Scope],
         connection: &PgConnection,
     ) -> Result<InternalUser, diesel::result::Error> {
+        diesel::update(internal_user::table.filter(id.eq(by_id)))
+            .set(scopes.eq(scope::to_strings(new)))
+            .get_result(connection)
+    }
+
+    // What they own has to go somewhere first, see `delete_with_heir` and
+    // `delete_self`.
+    fn delete(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        diesel::delete(dsl::internal_user.filter(id.eq(by_id))).execute(connection)
+    }
+
+    // Deletes the internal user and hands the users, roles, permissions and
+    // namespaces they own over to `heir_id`. Returns `None` without deleting
+    // anything when they are the last one able to manage internal users.
+    pub fn delete_with_heir(
+        by_id: i64,
+        heir_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Option<usize>, diesel::result::Error> {
+        connection.transaction(|| {
+            if InternalUser::lock_last_admin(by_id, connection)? {
+                return Ok(None);
+            }
+            InternalUser::lock(heir_id, connection)?;
+            diesel::update(user::table.filter(user::owner_id.eq(by_id)))
+                .set(user::owner_id.eq(heir_id))
+                .execute(connection)?;
+            diesel::update(role::table.filter(role::owner_id.eq(by_id)))
+                .set(role::owner_id.eq(heir_id))
+                .execute(connection)?;
+            diesel::update(permission::table.filter(permission::owner_id.eq(by_id)))
+                .set(permission::owner_id.eq(heir_id))
+                .execute(connection)?;
+            // The heir has to be able to open what they now own
+            let inherited: Vec<CreateNamespaceMember> =
+                diesel::update(namespace::table.filter(namespace::owner_id.eq(by_id)))
+                    .set(namespace::owner_id.eq(heir_id))
+                    .returning(namespace::id)
+                    .get_results::<i64>(connection)?
+                    .into_iter()
+                    .map(|namespace_id| CreateNamespaceMember {
+                        namespace_id,
+                        internal_user_id: heir_id,
+                    })
+                    .collect();
+            diesel::insert_into(namespace_member::table)
+                .values(inherited)
+                .on_conflict((
+                    namespace_member::namespace_id,
+                    namespace_member::internal_user_id,
+                ))
+                .do_nothing()
+                .execute(connection)?;
+            InternalUser::delete(by_id, connection).map(Some)
+        })
+    }
+
+    // Deletes the internal user on their own request. Namespaces they own
+    // pass to the member who has been in them longest, the ones nobody else
+    // is a member of are deleted, which needs `cascade` when they still hold
+    // users, roles or permissions. What they own in other namespaces passes
+    // to the owner of that namespace.
+    pub fn delete_self(
+        by_id: i64,
+        cascade: bool,
+        connection: &PgConnection,
+    ) -> Result<SelfDeletion, diesel::result::Error> {
+        connection.transaction(|| {
+            if InternalUser::lock_last_admin(by_id, connection)? {
+                return Ok(SelfDeletion::LastAdmin);
+            }
+            let owned: Vec<i64> = namespace::table
+                .filter(namespace::owner_id.eq(by_id))
+                .select(namespace::id)
+                .order(namespace::id)
+                .for_update()
+                .load(connection)?;
+            let mut heirs = Vec::with_capacity(owned.len());
+            for &namespace_id in &owned {
+                let heir: Option<i64> = namespace_member::table
+                    .filter(namespace_member::namespace_id.eq(namespace_id))
+                    .filter(namespace_member::internal_user_id.ne(by_id))
+                    .select(namespace_member::internal_user_id)
+                    .order(namespace_member::id)
+                    .first(connection)
+                    .optional()?;
+                if heir.is_none() && !cascade && Namespace::has_contents(namespace_id, connection)?
+                {
+                    return Ok(SelfDeletion::NamespaceInUse);
+                }
+                heirs.push((namespace_id, heir));
+            }
+            for (namespace_id, heir) in heirs {
+                match heir {
+                    Some(heir_id) => {
+                        diesel::update(namespace::table.find(namespace_id))
+                            .set(namespace::owner_id.eq(heir_id))
+                            .execute(connection)?;
+                    }
+                    // Nobody is left to subscribe to its decisions
+                    None => {
+                        Namespace::delete(namespace_id, connection)?;
+                    }
+                }
+            }
+            // What they own in other namespaces goes to the owner of that
+            // namespace
+            for owned_table in ["user", "role", "permission"].iter() {
+                diesel::sql_query(format!(
+                    r#"
+                    UPDATE "{0}" SET owner_id = n.owner_id
+                    FROM namespace n
+                    WHERE n.id = "{0}".namespace_id AND "{0}".owner_id = $1
+                    "#,
+                    owned_table
+                ))
+                .bind::<diesel::sql_types::BigInt, _>(by_id)
+                .execute(connection)?;
+            }
+            InternalUser::delete(by_id, connection).map(SelfDeletion::Deleted)
+        })
+    }
+
+    // Locks every admin and then `by_id`, which keeps two admins from
+    // deleting each other. Whether `by_id` is the last one able to manage
+    // internal users.
+    fn lock_last_admin(
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        let admins: Vec<i64> = dsl::internal_user
+            .filter(scopes.contains(vec![Scope::InternalManage.to_string()]))
+            .select(id)
+            .order(id)
+            .for_update()
+            .load(connection)?;
+        let deleted = InternalUser::lock(by_id, connection)?;
+        Ok(deleted.has_scope(Scope::InternalManage) && admins.iter().all(|&a| a == by_id))
+    }
+
+    fn lock(by_id: i64, connection: &PgConnection) -> Result<InternalUser, diesel::result::Error> {
         dsl::internal_user
-            .filter(auth_token.eq(by_auth_token))
+            .find(by_id)
+            .for_update()
             .first(connection)
     }
 
-    pub fn delete(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
-        diesel::delete(dsl::internal_user.filter(id.eq(by_id))).execute(connection)
+    // A changed email has to be verified again.
+    fn apply(
+        current: InternalUser,
+        changes: UpdateInternalUser,
+        connection: &PgConnection,
+    ) -> Result<InternalUser, diesel::result::Error> {
+        if changes.is_empty() {
+            return Ok(current);
+        }
+        let email_changed = changes
+            .email
+            .as_ref()
+            .is_some_and(|new_email| *new_email != current.email);
+        let updated = diesel::update(internal_user::table.filter(id.eq(current.id)))
+            .set(changes)
+            .get_result(connection)?;
+        match email_changed {
+            true => diesel::update(internal_user::table.filter(id.eq(current.id)))
+                .set(email_verified_on.eq(None::<DateTime<Utc>>))
+                .get_result(connection),
+            false => Ok(updated),
+        }
     }
 
+    // Only changes the given fields, and only logs the internal user out
+    // everywhere when the email or password changes. Returns `None` without
+    // changing anything when `precondition` refuses the current row.
     pub fn update(
         by_id: i64,
-        new: SubmitInternalUser,
+        patch: PatchInternalUser,
+        precondition: impl FnOnce(&InternalUser) -> bool,
         db_config: Arc<DatabaseConfig>,
         connection: &PgConnection,
+    ) -> Result<Option<InternalUser>, diesel::result::Error> {
+        connection.transaction(|| {
+            let current = InternalUser::lock(by_id, connection)?;
+            if !precondition(&current) {
+                return Ok(None);
+            }
+            let email_changed = patch
+                .email
+                .as_ref()
+                .is_some_and(|new_email| *new_email != current.email);
+            if email_changed || patch.password.is_some() {
+                AuthSession::revoke_all(by_id, None, connection)?;
+            }
+            let changes = UpdateInternalUser {
+                name: patch.name,
+                email: patch.email,
+                password: patch
+                    .password
+                    .map(|new_password| db_config.password_hasher.hash(&new_password)),
+            };
+            InternalUser::apply(current, changes, connection).map(Some)
+        })
+    }
+
+    // Sessions stay valid, a new email has to be verified again.
+    pub fn update_profile(
+        by_id: i64,
+        new_name: Option<String>,
+        new_email: Option<String>,
+        connection: &PgConnection,
     ) -> Result<InternalUser, diesel::result::Error> {
-        let user_salt = random_string(db_config.salt_length);
-        let hashed = hash_password(new.password, user_salt.clone(), db_config.iterations);
-        let new_token = random_string(db_config.api_key_length);
-        diesel::update(internal_user::table)
-            .set((
-                UpdateInternalUser {
-                    id: by_id,
-                    name: new.name,
-                    email: new.email,
-                    password: hashed.to_vec(),
-                    salt: user_salt,
-                },
-                auth_token.eq(new_token),
-            ))
+        connection.transaction(|| {
+            let current = InternalUser::lock(by_id, connection)?;
+            let changes = UpdateInternalUser {
+                name: new_name,
+                email: new_email,
+                password: None,
+            };
+            InternalUser::apply(current, changes, connection)
+        })
+    }
+
+    // Revokes every session of the internal user except `keep_session_id`.
+    pub fn set_password(
+        by_id: i64,
+        new_password: &str,
+        keep_session_id: Option<i64>,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<InternalUser, diesel::result::Error> {
+        connection.transaction(|| {
+            AuthSession::revoke_all(by_id, keep_session_id, connection)?;
+            diesel::update(internal_user::table.filter(id.eq(by_id)))
+                .set(password.eq(db_config.password_hasher.hash(new_password)))
+                .get_result(connection)
+        })
+    }
+
+    pub fn mark_email_verified(
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<InternalUser, diesel::result::Error> {
+        diesel::update(internal_user::table.filter(id.eq(by_id)))
+            .set(email_verified_on.eq(Utc::now()))
             .get_result(connection)
     }
 
-    pub fn update_auth_token(
+    // Checks the password against the scheme and parameters it was hashed
+    // with, not the ones currently configured.
+    pub fn verify_password(&self, submitted: &str) -> bool {
+        verify_password(submitted, &self.password)
+    }
+
+    pub fn needs_rehash(&self, db_config: &DatabaseConfig) -> bool {
+        needs_rehash(db_config.password_hasher.as_ref(), &self.password)
+    }
+
+    // Stores a fresh hash of an unchanged password, sessions stay valid.
+    pub fn rehash_password(
         by_id: i64,
+        unchanged_password: String,
         db_config: Arc<DatabaseConfig>,
         connection: &PgConnection,
     ) -> Result<InternalUser, diesel::result::Error> {
-        let new_token = random_string(db_config.api_key_length);
         diesel::update(internal_user::table.filter(id.eq(by_id)))
-            .set((
-                auth_token.eq(new_token),
-                last_login.eq(Utc::now()),
-                expires_on.eq(Utc::now() + Duration::minutes(120)),
-            ))
+            .set(password.eq(db_config.password_hasher.hash(&unchanged_password)))
             .get_result(connection)
     }
 }
+
+// An internal user with the default scopes and the password "password",
+// hashed cheaply.
+#[cfg(test)]
+pub fn test_internal_user(new_email: &str, connection: &PgConnection) -> InternalUser {
+    let new = SubmitInternalUser {
+        name: String::from("test"),
+        email: String::from(new_email),
+        password: String::from("password"),
+    };
+    let db_config = Arc::new(DatabaseConfig {
+        password_hasher: Box::new(
+            crate::utils::password::Argon2idHasher::new(8 * 1024, 1, 1).unwrap(),
+        ),
+        ..DatabaseConfig::default()
+    });
+    InternalUser::create(new, &Scope::DEFAULT, db_config, connection).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::models::role::Role;
+    use crate::database::models::tree::SubmitNode;
+    use crate::database::test_connection;
+    use crate::utils::password::{Argon2idHasher, Pbkdf2Hasher};
+    use std::num::NonZeroU32;
+
+    // What the PHC migration turns a PBKDF2 hash of "password" with 1000
+    // iterations and the salt "abcdefghijklmnopqrstuvwx" into.
+    const LEGACY_HASH: &str = "$pbkdf2-sha512$i=1000$YWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4$rjnFqqUHudLmGnvrqFxnszlt8cWPm5KObLRNDwSrEFSKJiauufYAzW8d4WzDzVTz5lastyCPeXSUG7XMk2o8qA";
+
+    fn internal_user() -> InternalUser {
+        InternalUser {
+            id: 1,
+            name: String::from("root"),
+            email: String::from("root@admin.com"),
+            password: String::new(),
+            created_on: Utc::now(),
+            last_login: None,
+            scopes: vec![String::from("internal:manage")],
+            totp_required: false,
+            email_verified_on: None,
+        }
+    }
+
+    #[test]
+    fn email_can_be_reused_after_it_changes_or_its_user_leaves() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let first = test_internal_user("reused@example.com", &connection);
+        InternalUser::update_profile(
+            first.id,
+            None,
+            Some(String::from("moved@example.com")),
+            &connection,
+        )
+        .unwrap();
+        let second = test_internal_user("reused@example.com", &connection);
+        assert_eq!(
+            Namespace::of_member(second.id, &connection).unwrap()[0].name,
+            "reused@example.com"
+        );
+        InternalUser::delete_with_heir(second.id, first.id, &connection)
+            .unwrap()
+            .unwrap();
+        test_internal_user("reused@example.com", &connection);
+    }
+
+    #[test]
+    fn heir_becomes_a_member_of_inherited_namespaces() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let heir = test_internal_user("heir@example.com", &connection);
+        let leaving = test_internal_user("leaving@example.com", &connection);
+        let shared = Namespace::create(
+            leaving.id,
+            SubmitNamespace {
+                name: String::from("shared"),
+            },
+            &connection,
+        )
+        .unwrap();
+        Namespace::add_members(shared.id, &[heir.id], &connection).unwrap();
+        InternalUser::delete_with_heir(leaving.id, heir.id, &connection)
+            .unwrap()
+            .unwrap();
+        let namespaces = Namespace::of_member(heir.id, &connection).unwrap();
+        let mut names: Vec<&str> = namespaces.iter().map(|n| n.name.as_str()).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["heir@example.com", "leaving@example.com", "shared"]
+        );
+        assert!(namespaces.iter().all(|n| n.owner_id == heir.id));
+    }
+
+    #[test]
+    fn fresh_internal_user_can_delete_themselves() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let iuser = test_internal_user("fresh@example.com", &connection);
+        assert_eq!(
+            InternalUser::delete_self(iuser.id, false, &connection).unwrap(),
+            SelfDeletion::Deleted(1)
+        );
+        assert!(InternalUser::find_by_id(iuser.id, &connection).is_err());
+    }
+
+    #[test]
+    fn leaving_hands_shared_namespaces_on() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let staying = test_internal_user("staying@example.com", &connection);
+        let leaving = test_internal_user("leaving-self@example.com", &connection);
+        let shared = Namespace::create(
+            leaving.id,
+            SubmitNamespace {
+                name: String::from("team"),
+            },
+            &connection,
+        )
+        .unwrap();
+        Namespace::add_members(shared.id, &[staying.id], &connection).unwrap();
+        let node = |path: &str| SubmitNode {
+            name: String::from(path),
+        };
+        let shared_role = Role::create(shared.id, leaving.id, node("eng"), &connection).unwrap();
+        let own = Namespace::of_member(leaving.id, &connection)
+            .unwrap()
+            .into_iter()
+            .find(|n| n.id != shared.id)
+            .unwrap();
+        let own_role = Role::create(own.id, leaving.id, node("eng"), &connection).unwrap();
+
+        assert_eq!(
+            InternalUser::delete_self(leaving.id, false, &connection).unwrap(),
+            SelfDeletion::NamespaceInUse
+        );
+        assert!(Role::find_by_id(own.id, own_role.id, &connection).is_ok());
+        assert_eq!(
+            InternalUser::delete_self(leaving.id, true, &connection).unwrap(),
+            SelfDeletion::Deleted(1)
+        );
+        assert!(Namespace::find_by_id(own.id, &connection).is_err());
+        assert_eq!(
+            Namespace::find_by_id(shared.id, &connection)
+                .unwrap()
+                .owner_id,
+            staying.id
+        );
+        assert_eq!(
+            Role::find_by_id(shared.id, shared_role.id, &connection)
+                .unwrap()
+                .owner_id,
+            staying.id
+        );
+    }
+
+    #[test]
+    fn legacy_pbkdf2_password_is_upgraded() {
+        let config = DatabaseConfig::default();
+        let iuser = InternalUser {
+            password: String::from(LEGACY_HASH),
+            ..internal_user()
+        };
+        assert!(iuser.verify_password("password"));
+        assert!(!iuser.verify_password("wrong"));
+        assert!(iuser.needs_rehash(&config));
+
+        let upgraded = InternalUser {
+            password: config.password_hasher.hash("password"),
+            ..internal_user()
+        };
+        assert!(upgraded.password.starts_with("$argon2id$"));
+        assert!(upgraded.verify_password("password"));
+        assert!(!upgraded.needs_rehash(&config));
+    }
+
+    #[test]
+    fn weaker_parameters_need_rehash() {
+        let weak = DatabaseConfig {
+            password_hasher: Box::new(Argon2idHasher::new(8 * 1024, 1, 1).unwrap()),
+            ..DatabaseConfig::default()
+        };
+        let iuser = InternalUser {
+            password: weak.password_hasher.hash("password"),
+            ..internal_user()
+        };
+        assert!(iuser.verify_password("password"));
+        assert!(!iuser.needs_rehash(&weak));
+        assert!(iuser.needs_rehash(&DatabaseConfig::default()));
+
+        let pbkdf2 = DatabaseConfig {
+            password_hasher: Box::new(Pbkdf2Hasher {
+                iterations: NonZeroU32::new(1000).unwrap(),
+            }),
+            ..DatabaseConfig::default()
+        };
+        let stronger_pbkdf2 = DatabaseConfig {
+            password_hasher: Box::new(Pbkdf2Hasher {
+                iterations: NonZeroU32::new(2000).unwrap(),
+            }),
+            ..DatabaseConfig::default()
+        };
+        let legacy = InternalUser {
+            password: String::from(LEGACY_HASH),
+            ..internal_user()
+        };
+        assert!(!legacy.needs_rehash(&pbkdf2));
+        assert!(legacy.needs_rehash(&stronger_pbkdf2));
+        let rehashed = InternalUser {
+            password: stronger_pbkdf2.password_hasher.hash("password"),
+            ..internal_user()
+        };
+        assert!(rehashed.verify_password("password"));
+        assert!(!rehashed.needs_rehash(&stronger_pbkdf2));
+    }
+}
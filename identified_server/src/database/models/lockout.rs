@@ -0,0 +1,257 @@
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::schema::lockout;
+
+pub const ACCOUNT: &str = "account";
+pub const IP_ADDRESS: &str = "ip";
+// Password reset mails asked for an email, limited like failed logins to the
+// account so nobody can flood an inbox with them
+pub const PASSWORD_RESET_MAIL: &str = "password_reset";
+
+// How failed logins slow down further attempts. Each failure doubles the wait
+// before the next attempt, from `base_delay` up to `max_delay`, and after
+// the maximum number of failures the subject is locked out for `lockout`.
+// Failures are forgotten after `reset` without any. A `max_ip_failures` of 0
+// stops counting per IP address, for when many people share one, as behind
+// a NAT or a proxy, where one of them could otherwise lock out the rest.
+pub struct LockoutPolicy {
+    pub max_account_failures: i32,
+    pub max_ip_failures: i32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub lockout: Duration,
+    pub reset: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> LockoutPolicy {
+        LockoutPolicy {
+            max_account_failures: 5,
+            max_ip_failures: 20,
+            base_delay: Duration::seconds(1),
+            max_delay: Duration::seconds(30),
+            lockout: Duration::minutes(15),
+            reset: Duration::minutes(60),
+        }
+    }
+}
+
+impl LockoutPolicy {
+    pub fn counts_ip_addresses(&self) -> bool {
+        self.max_ip_failures > 0
+    }
+
+    fn max_failures(&self, kind: &str) -> i32 {
+        match kind {
+            IP_ADDRESS => self.max_ip_failures,
+            _ => self.max_account_failures,
+        }
+    }
+
+    // How long to refuse logins after `failures` failures in a row.
+    pub fn delay(&self, kind: &str, failures: i32) -> Duration {
+        if failures >= self.max_failures(kind) {
+            return self.lockout;
+        }
+        let doublings = (failures - 1).clamp(0, 30);
+        let millis = self
+            .base_delay
+            .num_milliseconds()
+            .checked_mul(1 << doublings);
+        match millis.map(Duration::milliseconds) {
+            Some(delay) if delay < self.max_delay => delay,
+            _ => self.max_delay,
+        }
+    }
+}
+
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct Lockout {
+    pub id: i64,
+    // `account`, `ip` or `password_reset`
+    pub kind: String,
+    // The email or IP address that failed to log in, or the email a password
+    // reset was asked for
+    pub subject: String,
+    pub failures: i32,
+    pub last_failure: DateTime<Utc>,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[table_name = "lockout"]
+pub struct CreateLockout<'a> {
+    pub kind: &'a str,
+    pub subject: &'a str,
+    pub failures: i32,
+    pub last_failure: DateTime<Utc>,
+}
+
+// Emails only differ in case by accident, not to get more attempts.
+pub fn account_subject(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl Lockout {
+    // Subjects that failed recently, including ones no longer locked.
+    pub fn all(connection: &PgConnection) -> Result<Vec<Lockout>, diesel::result::Error> {
+        lockout::table
+            .order(lockout::last_failure.desc())
+            .load(connection)
+    }
+
+    // The furthest point in time any of `subjects` is locked until, if any is.
+    pub fn locked_until(
+        subjects: &[(&str, &str)],
+        connection: &PgConnection,
+    ) -> Result<Option<DateTime<Utc>>, diesel::result::Error> {
+        let mut until = None;
+        for (kind, subject) in subjects {
+            let locked_until: Option<Option<DateTime<Utc>>> = lockout::table
+                .filter(lockout::kind.eq(kind))
+                .filter(lockout::subject.eq(subject))
+                .filter(lockout::locked_until.gt(Utc::now()))
+                .select(lockout::locked_until)
+                .first(connection)
+                .optional()?;
+            until = until.max(locked_until.flatten());
+        }
+        Ok(until)
+    }
+
+    // Counts another failure against each of `subjects` and locks them for
+    // as long as the policy asks. Rows any email or address can create, so
+    // the ones whose failures are forgotten by now are removed on the way.
+    pub fn record_failure(
+        subjects: &[(&str, &str)],
+        policy: &LockoutPolicy,
+        connection: &PgConnection,
+    ) -> Result<(), diesel::result::Error> {
+        let now = Utc::now();
+        connection.transaction(|| {
+            diesel::delete(
+                lockout::table
+                    .filter(lockout::last_failure.le(now - policy.reset))
+                    .filter(
+                        lockout::locked_until
+                            .is_null()
+                            .or(lockout::locked_until.le(now)),
+                    ),
+            )
+            .execute(connection)?;
+            for &(kind, subject) in subjects {
+                diesel::insert_into(lockout::table)
+                    .values(CreateLockout {
+                        kind,
+                        subject,
+                        failures: 0,
+                        last_failure: now,
+                    })
+                    .on_conflict((lockout::kind, lockout::subject))
+                    .do_nothing()
+                    .execute(connection)?;
+                let current: Lockout = lockout::table
+                    .filter(lockout::kind.eq(kind))
+                    .filter(lockout::subject.eq(subject))
+                    .for_update()
+                    .first(connection)?;
+                let failures = match current.last_failure + policy.reset <= now {
+                    true => 1,
+                    false => current.failures + 1,
+                };
+                diesel::update(lockout::table.find(current.id))
+                    .set((
+                        lockout::failures.eq(failures),
+                        lockout::last_failure.eq(now),
+                        lockout::locked_until.eq(now + policy.delay(kind, failures)),
+                    ))
+                    .execute(connection)?;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn clear(
+        kind: &str,
+        subject: &str,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        diesel::delete(
+            lockout::table
+                .filter(lockout::kind.eq(kind))
+                .filter(lockout::subject.eq(subject)),
+        )
+        .execute(connection)
+    }
+
+    pub fn delete(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        let deleted = diesel::delete(lockout::table.find(by_id)).execute(connection)?;
+        match deleted {
+            0 => Err(diesel::result::Error::NotFound),
+            n => Ok(n),
+        }
+    }
+
+    pub fn delete_all(connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        diesel::delete(lockout::table).execute(connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_connection;
+
+    #[test]
+    fn delay_doubles_until_locked_out() {
+        let policy = LockoutPolicy::default();
+        assert_eq!(policy.delay(ACCOUNT, 1), Duration::seconds(1));
+        assert_eq!(policy.delay(ACCOUNT, 2), Duration::seconds(2));
+        assert_eq!(policy.delay(ACCOUNT, 4), Duration::seconds(8));
+        assert_eq!(policy.delay(ACCOUNT, 5), Duration::minutes(15));
+        assert_eq!(policy.delay(IP_ADDRESS, 5), Duration::seconds(16));
+        assert_eq!(policy.delay(IP_ADDRESS, 19), Duration::seconds(30));
+        assert_eq!(policy.delay(IP_ADDRESS, 20), Duration::minutes(15));
+        assert_eq!(policy.delay(IP_ADDRESS, 1000), Duration::minutes(15));
+    }
+
+    #[test]
+    fn forgotten_failures_are_pruned() {
+        let connection = match test_connection() {
+            Some(connection) => connection,
+            None => return,
+        };
+        let policy = LockoutPolicy::default();
+        let long_ago = Utc::now() - policy.reset - Duration::minutes(1);
+        for (subject, locked_until) in [
+            ("stale@example.com", None),
+            (
+                "locked@example.com",
+                Some(Utc::now() + Duration::minutes(1)),
+            ),
+        ]
+        .iter()
+        {
+            diesel::insert_into(lockout::table)
+                .values((
+                    lockout::kind.eq(ACCOUNT),
+                    lockout::subject.eq(subject),
+                    lockout::failures.eq(3),
+                    lockout::last_failure.eq(long_ago),
+                    lockout::locked_until.eq(locked_until),
+                ))
+                .execute(&connection)
+                .unwrap();
+        }
+        Lockout::record_failure(&[(ACCOUNT, "fresh@example.com")], &policy, &connection).unwrap();
+        let mut subjects: Vec<String> = Lockout::all(&connection)
+            .unwrap()
+            .into_iter()
+            .map(|lockout| lockout.subject)
+            .collect();
+        subjects.sort();
+        assert_eq!(subjects, vec!["fresh@example.com", "locked@example.com"]);
+    }
+}
@@ -0,0 +1,209 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::models::internal_user::InternalUser;
+use crate::database::schema::{
+    internal_user, namespace, namespace_member, permission, role, role_permission, user,
+    user_permission, user_role,
+};
+use crate::utils::common::distinct;
+
+// The tenant boundary. Users, roles and permissions each live in exactly one
+// namespace, internal users can only reach the namespaces they are a member
+// of.
+#[derive(Queryable, Serialize, Deserialize, Clone)]
+pub struct Namespace {
+    pub id: i64,
+    pub name: String,
+    // The internal user who created it
+    pub owner_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "namespace"]
+pub struct CreateNamespace {
+    pub name: String,
+    pub owner_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "namespace_member"]
+pub struct CreateNamespaceMember {
+    pub namespace_id: i64,
+    pub internal_user_id: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitNamespace {
+    pub name: String,
+}
+
+impl Namespace {
+    pub fn all(connection: &PgConnection) -> Result<Vec<Namespace>, diesel::result::Error> {
+        namespace::table.order(namespace::name).load(connection)
+    }
+
+    pub fn of_member(
+        by_iuser_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<Namespace>, diesel::result::Error> {
+        namespace::table
+            .inner_join(namespace_member::table)
+            .filter(namespace_member::internal_user_id.eq(by_iuser_id))
+            .select(namespace::all_columns)
+            .order(namespace::name)
+            .load(connection)
+    }
+
+    // The creator becomes its first member.
+    pub fn create(
+        by_owner_id: i64,
+        new: SubmitNamespace,
+        connection: &PgConnection,
+    ) -> Result<Namespace, diesel::result::Error> {
+        connection.transaction(|| {
+            let result: Namespace = diesel::insert_into(namespace::table)
+                .values(CreateNamespace {
+                    name: new.name,
+                    owner_id: by_owner_id,
+                })
+                .get_result(connection)?;
+            Namespace::add_members(result.id, &[by_owner_id], connection)?;
+            Ok(result)
+        })
+    }
+
+    pub fn find_by_id(
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Namespace, diesel::result::Error> {
+        namespace::table.find(by_id).first(connection)
+    }
+
+    // Not found unless `by_iuser_id` is a member, so non-members cannot tell
+    // which namespaces exist.
+    pub fn find_for_member(
+        by_iuser_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Namespace, diesel::result::Error> {
+        namespace::table
+            .inner_join(namespace_member::table)
+            .filter(namespace::id.eq(by_id))
+            .filter(namespace_member::internal_user_id.eq(by_iuser_id))
+            .select(namespace::all_columns)
+            .first(connection)
+    }
+
+    pub fn members(
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<InternalUser>, diesel::result::Error> {
+        internal_user::table
+            .inner_join(namespace_member::table)
+            .filter(namespace_member::namespace_id.eq(by_id))
+            .select(internal_user::all_columns)
+            .order(internal_user::id)
+            .load(connection)
+    }
+
+    // Internal users that already are a member are left untouched.
+    pub fn add_members(
+        by_id: i64,
+        by_iuser_ids: &[i64],
+        connection: &PgConnection,
+    ) -> Result<Vec<InternalUser>, diesel::result::Error> {
+        let iuser_ids = distinct(by_iuser_ids);
+        connection.transaction(|| {
+            Namespace::find_by_id(by_id, connection)?;
+            let existing: i64 = internal_user::table
+                .filter(internal_user::id.eq_any(&iuser_ids))
+                .count()
+                .get_result(connection)?;
+            if existing as usize != iuser_ids.len() {
+                return Err(diesel::result::Error::NotFound);
+            }
+            let new: Vec<CreateNamespaceMember> = iuser_ids
+                .iter()
+                .map(|&internal_user_id| CreateNamespaceMember {
+                    namespace_id: by_id,
+                    internal_user_id,
+                })
+                .collect();
+            diesel::insert_into(namespace_member::table)
+                .values(new)
+                .on_conflict((
+                    namespace_member::namespace_id,
+                    namespace_member::internal_user_id,
+                ))
+                .do_nothing()
+                .execute(connection)?;
+            Namespace::members(by_id, connection)
+        })
+    }
+
+    pub fn remove_members(
+        by_id: i64,
+        by_iuser_ids: &[i64],
+        connection: &PgConnection,
+    ) -> Result<Vec<InternalUser>, diesel::result::Error> {
+        connection.transaction(|| {
+            Namespace::find_by_id(by_id, connection)?;
+            diesel::delete(
+                namespace_member::table
+                    .filter(namespace_member::namespace_id.eq(by_id))
+                    .filter(namespace_member::internal_user_id.eq_any(by_iuser_ids)),
+            )
+            .execute(connection)?;
+            Namespace::members(by_id, connection)
+        })
+    }
+
+    // Whether any user, role or permission still lives in the namespace.
+    pub fn has_contents(
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        let users: i64 = user::table
+            .filter(user::namespace_id.eq(by_id))
+            .count()
+            .get_result(connection)?;
+        let roles: i64 = role::table
+            .filter(role::namespace_id.eq(by_id))
+            .count()
+            .get_result(connection)?;
+        let permissions: i64 = permission::table
+            .filter(permission::namespace_id.eq(by_id))
+            .count()
+            .get_result(connection)?;
+        Ok(users > 0 || roles > 0 || permissions > 0)
+    }
+
+    // Deletes the namespace together with everything living in it.
+    pub fn delete(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        connection.transaction(|| {
+            Namespace::find_by_id(by_id, connection)?;
+            let user_ids = user::table
+                .filter(user::namespace_id.eq(by_id))
+                .select(user::id);
+            let role_ids = role::table
+                .filter(role::namespace_id.eq(by_id))
+                .select(role::id);
+            diesel::delete(user_role::table.filter(user_role::user_id.eq_any(user_ids)))
+                .execute(connection)?;
+            diesel::delete(
+                user_permission::table.filter(user_permission::user_id.eq_any(user_ids)),
+            )
+            .execute(connection)?;
+            diesel::delete(
+                role_permission::table.filter(role_permission::role_id.eq_any(role_ids)),
+            )
+            .execute(connection)?;
+            diesel::delete(user::table.filter(user::namespace_id.eq(by_id))).execute(connection)?;
+            diesel::delete(role::table.filter(role::namespace_id.eq(by_id))).execute(connection)?;
+            diesel::delete(permission::table.filter(permission::namespace_id.eq(by_id)))
+                .execute(connection)?;
+            diesel::delete(namespace::table.find(by_id)).execute(connection)
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use diesel::prelude::*;
+use diesel_ltree::Ltree;
+use serde::{Deserialize, Serialize};
+
+use crate::database::schema::{permission, role_permission, user_permission};
+
+// Holding a permission implies holding all of its descendants, a grant of
+// `billing` allows `billing.invoices.read`.
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct Permission {
+    pub id: i64,
+    pub name: String,
+    // The internal user who created it
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "permission"]
+pub struct CreatePermission {
+    pub name: Ltree,
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+ltree_tree!(
+    Permission,
+    CreatePermission,
+    permission,
+    [
+        user_permission::permission_id,
+        role_permission::permission_id
+    ]
+);
+
+impl Permission {
+    // Descendants go along with the permission, only assignments anywhere in
+    // the subtree have to be confirmed.
+    pub fn in_use(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        Permission::has_assignments(by_namespace_id, by_id, connection)
+    }
+
+    // Renaming or moving the permission changes the grants it falls under,
+    // which can affect any user.
+    pub fn affected_users(
+        _by_namespace_id: i64,
+        _by_id: i64,
+        _connection: &PgConnection,
+    ) -> Result<Option<Vec<i64>>, diesel::result::Error> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,54 @@
+use diesel::prelude::*;
+use diesel_ltree::Ltree;
+use serde::{Deserialize, Serialize};
+
+use crate::database::models::user_role::UserRole;
+use crate::database::schema::{role, role_permission, user_role};
+
+// The ltree path doubles as the hierarchy, a role `eng.backend` inherits the
+// permissions of `eng`.
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct Role {
+    pub id: i64,
+    pub name: String,
+    // The internal user who created it
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "role"]
+pub struct CreateRole {
+    pub name: Ltree,
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+ltree_tree!(
+    Role,
+    CreateRole,
+    role,
+    [user_role::role_id, role_permission::role_id]
+);
+
+impl Role {
+    // Whether deleting the role would also affect descendants or assignments.
+    pub fn in_use(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        let subtree = Role::subtree_ids(by_namespace_id, by_id, connection)?;
+        Ok(subtree.len() > 1 || Role::has_assignments(by_namespace_id, by_id, connection)?)
+    }
+
+    // Renaming, moving or deleting the role only changes what the users
+    // holding a role in its subtree are allowed.
+    pub fn affected_users(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Option<Vec<i64>>, diesel::result::Error> {
+        UserRole::inheriting_users(by_namespace_id, by_id, connection).map(Some)
+    }
+}
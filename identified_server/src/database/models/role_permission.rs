@@ -0,0 +1,29 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::models::permission::Permission;
+use crate::database::models::role::Role;
+use crate::database::schema::role_permission;
+
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct RolePermission {
+    pub id: i64,
+    pub role_id: i64,
+    pub permission_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "role_permission"]
+pub struct CreateRolePermission {
+    pub role_id: i64,
+    pub permission_id: i64,
+}
+
+assignment!(
+    RolePermission,
+    CreateRolePermission,
+    role_permission,
+    Role.role_id,
+    Permission.permission_id,
+    permissions_of
+);
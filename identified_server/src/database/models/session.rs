@@ -0,0 +1,233 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::database::models::internal_user::InternalUser;
+use crate::database::schema::{internal_user, session};
+use crate::database::DatabaseConfig;
+use crate::utils::common::{hash_token, random_string};
+
+// One row per issued token, so an internal user can be logged in from several
+// places at once. Only the keyed hash of the token is stored.
+#[derive(Queryable)]
+pub struct AuthSession {
+    pub id: i64,
+    pub internal_user_id: i64,
+    pub token: String,
+    pub created_on: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "session"]
+pub struct CreateAuthSession {
+    pub internal_user_id: i64,
+    pub token: String,
+    pub created_on: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PublicAuthSession {
+    pub id: i64,
+    pub created_on: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires_on: DateTime<Utc>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    // Whether this is the session the request was made with
+    pub current: bool,
+}
+
+impl PublicAuthSession {
+    pub fn new(auth_session: AuthSession, current_id: Option<i64>) -> PublicAuthSession {
+        PublicAuthSession {
+            current: Some(auth_session.id) == current_id,
+            id: auth_session.id,
+            created_on: auth_session.created_on,
+            last_seen: auth_session.last_seen,
+            expires_on: auth_session.expires_on,
+            user_agent: auth_session.user_agent,
+            ip_address: auth_session.ip_address,
+        }
+    }
+}
+
+// Where a login came from, as far as the server can tell.
+pub struct SessionOrigin {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl AuthSession {
+    pub fn all(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<AuthSession>, diesel::result::Error> {
+        session::table
+            .filter(session::internal_user_id.eq(by_internal_user_id))
+            .filter(session::expires_on.gt(Utc::now()))
+            .order(session::last_seen.desc())
+            .load(connection)
+    }
+
+    // Returns the raw token next to the session, it cannot be recovered later.
+    // Expired sessions of the internal user are cleaned up on the way.
+    pub fn create(
+        by_internal_user_id: i64,
+        origin: SessionOrigin,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(AuthSession, String), diesel::result::Error> {
+        let new_token = random_string(db_config.api_key_length);
+        let now = Utc::now();
+        connection.transaction(|| {
+            diesel::delete(
+                session::table
+                    .filter(session::internal_user_id.eq(by_internal_user_id))
+                    .filter(session::expires_on.le(now)),
+            )
+            .execute(connection)?;
+            diesel::update(internal_user::table.find(by_internal_user_id))
+                .set(internal_user::last_login.eq(now))
+                .execute(connection)?;
+            let auth_session = diesel::insert_into(session::table)
+                .values(CreateAuthSession {
+                    internal_user_id: by_internal_user_id,
+                    token: hash_token(&db_config.token_key, &new_token),
+                    created_on: now,
+                    last_seen: now,
+                    expires_on: now + db_config.token_lifetime,
+                    user_agent: origin.user_agent,
+                    ip_address: origin.ip_address,
+                })
+                .get_result(connection)?;
+            Ok((auth_session, new_token))
+        })
+    }
+
+    // Tokens are only stored as a keyed hash, so a leaked database does not
+    // hand out live sessions.
+    pub fn find_by_token(
+        by_token: String,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(AuthSession, InternalUser), diesel::result::Error> {
+        session::table
+            .inner_join(internal_user::table)
+            .filter(session::token.eq(hash_token(&db_config.token_key, &by_token)))
+            .first(connection)
+    }
+
+    pub fn touch(by_id: i64, connection: &PgConnection) -> Result<usize, diesel::result::Error> {
+        diesel::update(session::table.find(by_id))
+            .set(session::last_seen.eq(Utc::now()))
+            .execute(connection)
+    }
+
+    // Swaps the token of an existing session and slides its expiry forward.
+    pub fn refresh(
+        by_id: i64,
+        db_config: Arc<DatabaseConfig>,
+        connection: &PgConnection,
+    ) -> Result<(AuthSession, String), diesel::result::Error> {
+        let new_token = random_string(db_config.api_key_length);
+        let now = Utc::now();
+        let auth_session = diesel::update(session::table.find(by_id))
+            .set((
+                session::token.eq(hash_token(&db_config.token_key, &new_token)),
+                session::last_seen.eq(now),
+                session::expires_on.eq(now + db_config.token_lifetime),
+            ))
+            .get_result(connection)?;
+        Ok((auth_session, new_token))
+    }
+
+    // Only deletes the session if it belongs to `by_internal_user_id`.
+    pub fn revoke(
+        by_internal_user_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        let deleted = diesel::delete(
+            session::table
+                .filter(session::internal_user_id.eq(by_internal_user_id))
+                .filter(session::id.eq(by_id)),
+        )
+        .execute(connection)?;
+        match deleted {
+            0 => Err(diesel::result::Error::NotFound),
+            n => Ok(n),
+        }
+    }
+
+    // Revokes every session of the internal user, except `keep_id` if given.
+    pub fn revoke_all(
+        by_internal_user_id: i64,
+        keep_id: Option<i64>,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        let sessions = session::table.filter(session::internal_user_id.eq(by_internal_user_id));
+        match keep_id {
+            Some(keep_id) => {
+                diesel::delete(sessions.filter(session::id.ne(keep_id))).execute(connection)
+            }
+            None => diesel::delete(sessions).execute(connection),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_on <= Utc::now()
+    }
+}
+
+// An authorization token for handler tests, only valid with the same
+// `db_config`, whose key is random by default.
+#[cfg(test)]
+pub fn test_token(
+    by_internal_user_id: i64,
+    db_config: Arc<DatabaseConfig>,
+    connection: &PgConnection,
+) -> String {
+    let origin = SessionOrigin {
+        user_agent: None,
+        ip_address: None,
+    };
+    AuthSession::create(by_internal_user_id, origin, db_config, connection)
+        .unwrap()
+        .1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn expiring_in(lifetime: Duration) -> AuthSession {
+        let now = Utc::now();
+        AuthSession {
+            id: 1,
+            internal_user_id: 1,
+            token: String::new(),
+            created_on: now - Duration::minutes(120),
+            last_seen: now,
+            expires_on: now + lifetime,
+            user_agent: None,
+            ip_address: None,
+        }
+    }
+
+    #[test]
+    fn sessions_expire_at_expires_on() {
+        assert!(!expiring_in(Duration::minutes(1)).is_expired());
+        assert!(expiring_in(Duration::zero()).is_expired());
+        assert!(expiring_in(Duration::minutes(-1)).is_expired());
+    }
+}
@@ -0,0 +1,263 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use diesel::prelude::*;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::database::models::internal_user::InternalUser;
+use crate::database::schema::{internal_user, recovery_code, totp};
+use crate::database::DatabaseConfig;
+use crate::utils::common::{hash_token, random_string, to_hex};
+use crate::utils::totp as otp;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+// How long the challenge handed out after the password step stays valid
+pub const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+// Time-based one-time passwords of an internal user, see `utils::totp`.
+#[derive(Queryable)]
+pub struct Totp {
+    pub internal_user_id: i64,
+    pub secret: String,
+    pub confirmed_on: Option<DateTime<Utc>>,
+    pub last_step: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "totp"]
+pub struct CreateTotp {
+    pub internal_user_id: i64,
+    pub secret: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "recovery_code"]
+pub struct CreateRecoveryCode {
+    pub internal_user_id: i64,
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitCode {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitTotpRequired {
+    pub required: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitChallenge {
+    pub challenge: String,
+    // Either a current code or one of the recovery codes
+    pub code: String,
+}
+
+// Recovery codes are random enough that a plain hash suffices, unlike tokens
+// they have to survive a change of `tokens.secret`.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    to_hex(digest::digest(&digest::SHA256, normalized.as_bytes()).as_ref())
+}
+
+fn generate_recovery_code() -> String {
+    let code = random_string(10).to_lowercase();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+impl Totp {
+    pub fn find(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Option<Totp>, diesel::result::Error> {
+        totp::table
+            .find(by_internal_user_id)
+            .first(connection)
+            .optional()
+    }
+
+    // Whether two-factor authentication is in use for the internal user.
+    pub fn is_enabled(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        Ok(Totp::find(by_internal_user_id, connection)?.is_some_and(|t| t.is_confirmed()))
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_on.is_some()
+    }
+
+    // Starts over with a new secret, replacing an enrollment that was never
+    // confirmed.
+    pub fn enroll(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Totp, diesel::result::Error> {
+        diesel::insert_into(totp::table)
+            .values(CreateTotp {
+                internal_user_id: by_internal_user_id,
+                secret: otp::generate_secret(),
+            })
+            .on_conflict(totp::internal_user_id)
+            .do_update()
+            .set((
+                totp::secret.eq(otp::generate_secret()),
+                totp::confirmed_on.eq(None::<DateTime<Utc>>),
+                totp::last_step.eq(0),
+            ))
+            .get_result(connection)
+    }
+
+    // Returns the fresh recovery codes, they cannot be recovered later.
+    pub fn confirm(
+        &self,
+        code: &str,
+        connection: &PgConnection,
+    ) -> Result<Option<Vec<String>>, diesel::result::Error> {
+        let step = match otp::verify(&self.secret, code, Utc::now().timestamp(), self.last_step) {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+        connection.transaction(|| {
+            diesel::update(totp::table.find(self.internal_user_id))
+                .set((totp::confirmed_on.eq(Utc::now()), totp::last_step.eq(step)))
+                .execute(connection)?;
+            Totp::regenerate_recovery_codes(self.internal_user_id, connection).map(Some)
+        })
+    }
+
+    // Accepts a code from the authenticator or an unused recovery code, which
+    // is used up.
+    pub fn verify(
+        &self,
+        code: &str,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error> {
+        if let Some(step) = otp::verify(&self.secret, code, Utc::now().timestamp(), self.last_step)
+        {
+            // Guards against the same code being used by two requests at once
+            let updated = diesel::update(
+                totp::table
+                    .find(self.internal_user_id)
+                    .filter(totp::last_step.lt(step)),
+            )
+            .set(totp::last_step.eq(step))
+            .execute(connection)?;
+            return Ok(updated == 1);
+        }
+        let used = diesel::delete(
+            recovery_code::table
+                .filter(recovery_code::internal_user_id.eq(self.internal_user_id))
+                .filter(recovery_code::code.eq(hash_recovery_code(code))),
+        )
+        .execute(connection)?;
+        Ok(used > 0)
+    }
+
+    pub fn recovery_codes_left(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<i64, diesel::result::Error> {
+        recovery_code::table
+            .filter(recovery_code::internal_user_id.eq(by_internal_user_id))
+            .count()
+            .get_result(connection)
+    }
+
+    // Replaces every recovery code of the internal user.
+    pub fn regenerate_recovery_codes(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<String>, diesel::result::Error> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| generate_recovery_code())
+            .collect();
+        let new: Vec<CreateRecoveryCode> = codes
+            .iter()
+            .map(|code| CreateRecoveryCode {
+                internal_user_id: by_internal_user_id,
+                code: hash_recovery_code(code),
+            })
+            .collect();
+        connection.transaction(|| {
+            diesel::delete(
+                recovery_code::table
+                    .filter(recovery_code::internal_user_id.eq(by_internal_user_id)),
+            )
+            .execute(connection)?;
+            diesel::insert_into(recovery_code::table)
+                .values(new)
+                .execute(connection)?;
+            Ok(codes)
+        })
+    }
+
+    // Removes the secret and the recovery codes.
+    pub fn disable(
+        by_internal_user_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        connection.transaction(|| {
+            diesel::delete(
+                recovery_code::table
+                    .filter(recovery_code::internal_user_id.eq(by_internal_user_id)),
+            )
+            .execute(connection)?;
+            diesel::delete(totp::table.find(by_internal_user_id)).execute(connection)
+        })
+    }
+
+    pub fn set_required(
+        by_internal_user_id: i64,
+        required: bool,
+        connection: &PgConnection,
+    ) -> Result<InternalUser, diesel::result::Error> {
+        diesel::update(internal_user::table.find(by_internal_user_id))
+            .set(internal_user::totp_required.eq(required))
+            .get_result(connection)
+    }
+}
+
+// Handed out once the password checks out, to be exchanged for a session
+// together with a code. Nothing is stored, the challenge is signed instead.
+// Signing over the password hash voids it when the password changes.
+pub fn create_challenge(
+    iuser: &InternalUser,
+    db_config: Arc<DatabaseConfig>,
+) -> (String, DateTime<Utc>) {
+    let expires_on = Utc::now() + Duration::minutes(CHALLENGE_LIFETIME_MINUTES);
+    let payload = format!("{}.{}", iuser.id, expires_on.timestamp());
+    let signature = hash_token(
+        &db_config.token_key,
+        &format!("{}.{}", payload, iuser.password),
+    );
+    (format!("{}.{}", payload, signature), expires_on)
+}
+
+pub fn verify_challenge(
+    challenge: &str,
+    db_config: Arc<DatabaseConfig>,
+    connection: &PgConnection,
+) -> Option<InternalUser> {
+    let mut parts = challenge.splitn(3, '.');
+    let id: i64 = parts.next()?.parse().ok()?;
+    let expires: i64 = parts.next()?.parse().ok()?;
+    let signature = parts.next()?;
+    if Utc.timestamp_opt(expires, 0).single()? <= Utc::now() {
+        return None;
+    }
+    let iuser = InternalUser::find_by_id(id, connection).ok()?;
+    let expected = hash_token(
+        &db_config.token_key,
+        &format!("{}.{}.{}", id, expires, iuser.password),
+    );
+    let matches =
+        ring::constant_time::verify_slices_are_equal(expected.as_bytes(), signature.as_bytes());
+    matches.ok().map(|_| iuser)
+}
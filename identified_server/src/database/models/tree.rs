@@ -0,0 +1,383 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::common::{is_ltree_descendant, ltree_label};
+
+// Roles and permissions are both trees of ltree paths inside a namespace,
+// where the path doubles as the hierarchy. Everything that only depends on the
+// path is shared through this trait, which `ltree_tree!` implements.
+pub trait Tree: Serialize + Send + Sized + 'static {
+    fn id(&self) -> i64;
+    fn name(&self) -> &str;
+    fn all(
+        by_namespace_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<Self>, diesel::result::Error>;
+    fn create(
+        by_namespace_id: i64,
+        by_owner_id: i64,
+        new: SubmitNode,
+        connection: &PgConnection,
+    ) -> Result<Self, diesel::result::Error>;
+    fn find_by_id(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Self, diesel::result::Error>;
+    fn subtree(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<Self>, diesel::result::Error>;
+    fn move_subtree(
+        by_namespace_id: i64,
+        by_id: i64,
+        new_name: String,
+        connection: &PgConnection,
+    ) -> Result<Self, diesel::result::Error>;
+    // The users whose decisions can change when the subtree is renamed, moved
+    // or deleted, `None` when that can be any user of the namespace
+    fn affected_users(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Option<Vec<i64>>, diesel::result::Error>;
+    // Whether deleting has to be confirmed with `cascade`
+    fn in_use(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<bool, diesel::result::Error>;
+    fn delete(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitNode {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MoveNode {
+    pub parent_id: Option<i64>,
+}
+
+// The path after renaming `old` to `new`, unless that would put it inside
+// its own subtree.
+pub fn renamed_path(old: &str, new: String) -> Option<String> {
+    match new != old && is_ltree_descendant(&new, old) {
+        true => None,
+        false => Some(new),
+    }
+}
+
+// The path after moving `old` below `parent`, or to the top without one,
+// unless `parent` lies inside the subtree being moved.
+pub fn moved_path(old: &str, parent: Option<&str>) -> Option<String> {
+    match parent {
+        Some(parent) if is_ltree_descendant(parent, old) => None,
+        Some(parent) => Some(format!("{}.{}", parent, ltree_label(old))),
+        None => Some(ltree_label(old).to_string()),
+    }
+}
+
+// Implements `Tree` for `$model`, stored in `$table` with the `id`, `name`,
+// `owner_id` and `namespace_id` columns and inserted through `$create`. The
+// `$assignment` columns refer to the model and are removed along with it.
+// `$model::affected_users` and `$model::in_use` are left to the model.
+macro_rules! ltree_tree {
+    ($model:ident, $create:ident, $table:ident, [$($assignment:ident :: $column:ident),+]) => {
+        // Postgres cannot send ltree values over the binary protocol, so the
+        // name is always selected as text.
+        type Columns = (
+            $table::id,
+            $crate::database::functions::ltree2text::HelperType<$table::name>,
+            $table::owner_id,
+            $table::namespace_id,
+        );
+
+        fn columns() -> Columns {
+            (
+                $table::id,
+                $crate::database::functions::ltree2text($table::name),
+                $table::owner_id,
+                $table::namespace_id,
+            )
+        }
+
+        impl $model {
+            pub fn all(
+                by_namespace_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<$model>, diesel::result::Error> {
+                $table::table
+                    .select(columns())
+                    .filter($table::namespace_id.eq(by_namespace_id))
+                    .order($table::name)
+                    .load(connection)
+            }
+
+            pub fn create(
+                by_namespace_id: i64,
+                by_owner_id: i64,
+                new: $crate::database::models::tree::SubmitNode,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                diesel::insert_into($table::table)
+                    .values($create {
+                        name: diesel_ltree::Ltree(new.name),
+                        owner_id: by_owner_id,
+                        namespace_id: by_namespace_id,
+                    })
+                    .returning(columns())
+                    .get_result(connection)
+            }
+
+            pub fn find_by_id(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                $table::table
+                    .select(columns())
+                    .find(by_id)
+                    .filter($table::namespace_id.eq(by_namespace_id))
+                    .first(connection)
+            }
+
+            pub fn find_by_ids(
+                by_namespace_id: i64,
+                by_ids: &[i64],
+                connection: &PgConnection,
+            ) -> Result<Vec<$model>, diesel::result::Error> {
+                $table::table
+                    .select(columns())
+                    .filter($table::namespace_id.eq(by_namespace_id))
+                    .filter($table::id.eq_any(by_ids))
+                    .order($table::name)
+                    .load(connection)
+            }
+
+            // The node itself followed by all of its descendants.
+            pub fn subtree(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<$model>, diesel::result::Error> {
+                use diesel_ltree::LtreeExtensions;
+                let root = $model::find_by_id(by_namespace_id, by_id, connection)?;
+                $table::table
+                    .select(columns())
+                    .filter($table::namespace_id.eq(by_namespace_id))
+                    .filter($table::name.contained_by(diesel_ltree::Ltree(root.name)))
+                    .order($table::name)
+                    .load(connection)
+            }
+
+            pub fn subtree_ids(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<i64>, diesel::result::Error> {
+                Ok($model::subtree(by_namespace_id, by_id, connection)?
+                    .into_iter()
+                    .map(|node| node.id)
+                    .collect())
+            }
+
+            // Renames the node and rewrites the paths of all of its
+            // descendants, so the subtree keeps its shape under the new name.
+            pub fn move_subtree(
+                by_namespace_id: i64,
+                by_id: i64,
+                new_name: String,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                use diesel_ltree::LtreeExtensions;
+                connection.transaction(|| {
+                    let old = $model::find_by_id(by_namespace_id, by_id, connection)?;
+                    // `subltree` refuses an empty range, so the node itself
+                    // is renamed on its own
+                    diesel::update(
+                        $table::table
+                            .filter($table::namespace_id.eq(by_namespace_id))
+                            .filter($table::name.contained_by(diesel_ltree::Ltree(old.name.clone())))
+                            .filter($table::id.ne(by_id)),
+                    )
+                    .set($table::name.eq(diesel_ltree::Ltree(new_name.clone()).concat(
+                        diesel_ltree::subltree(
+                            $table::name,
+                            $crate::utils::common::ltree_depth(&old.name),
+                            diesel_ltree::nlevel($table::name),
+                        ),
+                    )))
+                    .execute(connection)?;
+                    diesel::update($table::table.find(by_id))
+                        .set($table::name.eq(diesel_ltree::Ltree(new_name)))
+                        .execute(connection)?;
+                    $model::find_by_id(by_namespace_id, by_id, connection)
+                })
+            }
+
+            // Whether any node in the subtree is still assigned.
+            pub fn has_assignments(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<bool, diesel::result::Error> {
+                let ids = $model::subtree_ids(by_namespace_id, by_id, connection)?;
+                let mut assigned: i64 = 0;
+                $(
+                    assigned += $assignment::table
+                        .filter($assignment::$column.eq_any(&ids))
+                        .count()
+                        .get_result::<i64>(connection)?;
+                )+
+                Ok(assigned > 0)
+            }
+
+            // Deletes the node, its descendants and every assignment
+            // referencing them.
+            pub fn delete(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<usize, diesel::result::Error> {
+                connection.transaction(|| {
+                    let ids = $model::subtree_ids(by_namespace_id, by_id, connection)?;
+                    $(
+                        diesel::delete($assignment::table.filter($assignment::$column.eq_any(&ids)))
+                            .execute(connection)?;
+                    )+
+                    diesel::delete($table::table.filter($table::id.eq_any(&ids))).execute(connection)
+                })
+            }
+        }
+
+        impl $crate::database::models::tree::Tree for $model {
+            fn id(&self) -> i64 {
+                self.id
+            }
+
+            fn name(&self) -> &str {
+                &self.name
+            }
+
+            fn all(
+                by_namespace_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<$model>, diesel::result::Error> {
+                $model::all(by_namespace_id, connection)
+            }
+
+            fn create(
+                by_namespace_id: i64,
+                by_owner_id: i64,
+                new: $crate::database::models::tree::SubmitNode,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                $model::create(by_namespace_id, by_owner_id, new, connection)
+            }
+
+            fn find_by_id(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                $model::find_by_id(by_namespace_id, by_id, connection)
+            }
+
+            fn subtree(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Vec<$model>, diesel::result::Error> {
+                $model::subtree(by_namespace_id, by_id, connection)
+            }
+
+            fn move_subtree(
+                by_namespace_id: i64,
+                by_id: i64,
+                new_name: String,
+                connection: &PgConnection,
+            ) -> Result<$model, diesel::result::Error> {
+                $model::move_subtree(by_namespace_id, by_id, new_name, connection)
+            }
+
+            fn affected_users(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<Option<Vec<i64>>, diesel::result::Error> {
+                $model::affected_users(by_namespace_id, by_id, connection)
+            }
+
+            fn in_use(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<bool, diesel::result::Error> {
+                $model::in_use(by_namespace_id, by_id, connection)
+            }
+
+            fn delete(
+                by_namespace_id: i64,
+                by_id: i64,
+                connection: &PgConnection,
+            ) -> Result<usize, diesel::result::Error> {
+                $model::delete(by_namespace_id, by_id, connection)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renaming_into_own_subtree_is_refused() {
+        assert_eq!(
+            renamed_path("eng", String::from("staff")),
+            Some(String::from("staff"))
+        );
+        assert_eq!(
+            renamed_path("eng", String::from("eng")),
+            Some(String::from("eng"))
+        );
+        assert_eq!(
+            renamed_path("eng.backend", String::from("eng")),
+            Some(String::from("eng"))
+        );
+        assert_eq!(
+            renamed_path("eng", String::from("engineering")),
+            Some(String::from("engineering"))
+        );
+        assert_eq!(renamed_path("eng", String::from("eng.backend")), None);
+    }
+
+    #[test]
+    fn moving_keeps_the_label() {
+        assert_eq!(
+            moved_path("eng.backend", Some("staff")),
+            Some(String::from("staff.backend"))
+        );
+        assert_eq!(
+            moved_path("eng.backend", None),
+            Some(String::from("backend"))
+        );
+        assert_eq!(
+            moved_path("eng.backend", Some("engineering")),
+            Some(String::from("engineering.backend"))
+        );
+    }
+
+    #[test]
+    fn moving_into_own_subtree_is_refused() {
+        assert_eq!(moved_path("eng", Some("eng")), None);
+        assert_eq!(moved_path("eng", Some("eng.backend.api")), None);
+    }
+}
@@ -0,0 +1,116 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::schema::{user, user_permission, user_role};
+use crate::utils::common::nullable;
+
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct User {
+    pub id: i64,
+    pub name: Option<String>,
+    // The internal user who created it
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "user"]
+pub struct CreateUser {
+    pub name: Option<String>,
+    pub owner_id: i64,
+    pub namespace_id: i64,
+}
+
+// `None` leaves the name as it is, `Some(None)` clears it.
+#[derive(AsChangeset)]
+#[table_name = "user"]
+pub struct UpdateUser {
+    pub name: Option<Option<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SubmitUser {
+    pub name: Option<String>,
+}
+
+// Left out fields stay as they are, `"name": null` clears the name.
+#[derive(Serialize, Deserialize)]
+pub struct PatchUser {
+    #[serde(default, deserialize_with = "nullable")]
+    pub name: Option<Option<String>>,
+}
+
+// Every query is scoped to a namespace, so the users of one namespace can
+// never be seen or touched through another.
+impl User {
+    pub fn all(
+        by_namespace_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<User>, diesel::result::Error> {
+        user::table
+            .filter(user::namespace_id.eq(by_namespace_id))
+            .order(user::id)
+            .load(connection)
+    }
+
+    pub fn create(
+        by_namespace_id: i64,
+        by_owner_id: i64,
+        new: SubmitUser,
+        connection: &PgConnection,
+    ) -> Result<User, diesel::result::Error> {
+        diesel::insert_into(user::table)
+            .values(CreateUser {
+                name: new.name,
+                owner_id: by_owner_id,
+                namespace_id: by_namespace_id,
+            })
+            .get_result(connection)
+    }
+
+    pub fn find_by_id(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<User, diesel::result::Error> {
+        user::table
+            .find(by_id)
+            .filter(user::namespace_id.eq(by_namespace_id))
+            .first(connection)
+    }
+
+    pub fn update(
+        by_namespace_id: i64,
+        by_id: i64,
+        new: PatchUser,
+        connection: &PgConnection,
+    ) -> Result<User, diesel::result::Error> {
+        // An empty changeset is not a valid query
+        if new.name.is_none() {
+            return User::find_by_id(by_namespace_id, by_id, connection);
+        }
+        diesel::update(
+            user::table
+                .find(by_id)
+                .filter(user::namespace_id.eq(by_namespace_id)),
+        )
+        .set(UpdateUser { name: new.name })
+        .get_result(connection)
+    }
+
+    // Removes the user together with its role and permission assignments.
+    pub fn delete(
+        by_namespace_id: i64,
+        by_id: i64,
+        connection: &PgConnection,
+    ) -> Result<usize, diesel::result::Error> {
+        connection.transaction(|| {
+            User::find_by_id(by_namespace_id, by_id, connection)?;
+            diesel::delete(user_role::table.filter(user_role::user_id.eq(by_id)))
+                .execute(connection)?;
+            diesel::delete(user_permission::table.filter(user_permission::user_id.eq(by_id)))
+                .execute(connection)?;
+            diesel::delete(user::table.find(by_id)).execute(connection)
+        })
+    }
+}
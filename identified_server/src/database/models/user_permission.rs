@@ -0,0 +1,29 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::models::permission::Permission;
+use crate::database::models::user::User;
+use crate::database::schema::user_permission;
+
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct UserPermission {
+    pub id: i64,
+    pub user_id: i64,
+    pub permission_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_permission"]
+pub struct CreateUserPermission {
+    pub user_id: i64,
+    pub permission_id: i64,
+}
+
+assignment!(
+    UserPermission,
+    CreateUserPermission,
+    user_permission,
+    User.user_id,
+    Permission.permission_id,
+    permissions_of
+);
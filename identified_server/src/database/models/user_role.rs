@@ -0,0 +1,48 @@
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::database::models::role::Role;
+use crate::database::models::user::User;
+use crate::database::schema::user_role;
+
+#[derive(Queryable, Serialize, Deserialize)]
+pub struct UserRole {
+    pub id: i64,
+    pub user_id: i64,
+    pub role_id: i64,
+}
+
+#[derive(Insertable)]
+#[table_name = "user_role"]
+pub struct CreateUserRole {
+    pub user_id: i64,
+    pub role_id: i64,
+}
+
+assignment!(
+    UserRole,
+    CreateUserRole,
+    user_role,
+    User.user_id,
+    Role.role_id,
+    roles_of
+);
+
+impl UserRole {
+    // Users holding the role, or any of the roles inheriting from it.
+    pub fn inheriting_users(
+        by_namespace_id: i64,
+        by_role_id: i64,
+        connection: &PgConnection,
+    ) -> Result<Vec<i64>, diesel::result::Error> {
+        let role_ids: Vec<i64> = Role::subtree(by_namespace_id, by_role_id, connection)?
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        user_role::table
+            .filter(user_role::role_id.eq_any(role_ids))
+            .select(user_role::user_id)
+            .distinct()
+            .load(connection)
+    }
+}
@@ -1,3 +1,36 @@
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    api_key (id) {
+        id -> Int8,
+        internal_user_id -> Int8,
+        name -> Text,
+        prefix -> Text,
+        key -> Text,
+        scopes -> Array<Text>,
+        namespace_ids -> Array<Int8>,
+        created_on -> Timestamptz,
+        expires_on -> Nullable<Timestamptz>,
+        last_used -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    email_token (id) {
+        id -> Int8,
+        internal_user_id -> Int8,
+        purpose -> Text,
+        email -> Text,
+        token -> Text,
+        created_on -> Timestamptz,
+        expires_on -> Timestamptz,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use diesel_ltree::*;
@@ -6,13 +39,48 @@ table! {
         id -> Int8,
         name -> Text,
         email -> Text,
-        password -> Bytea,
-        salt -> Text,
+        password -> Text,
         created_on -> Timestamptz,
         last_login -> Nullable<Timestamptz>,
-        auth_token -> Nullable<Text>,
-        expires_on -> Nullable<Timestamptz>,
-        admin -> Bool,
+        scopes -> Array<Text>,
+        totp_required -> Bool,
+        email_verified_on -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    lockout (id) {
+        id -> Int8,
+        kind -> Text,
+        subject -> Text,
+        failures -> Int4,
+        last_failure -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    namespace (id) {
+        id -> Int8,
+        name -> Text,
+        owner_id -> Int8,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    namespace_member (id) {
+        id -> Int8,
+        namespace_id -> Int8,
+        internal_user_id -> Int8,
     }
 }
 
@@ -24,6 +92,18 @@ table! {
         id -> Int8,
         name -> Ltree,
         owner_id -> Int8,
+        namespace_id -> Int8,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    recovery_code (id) {
+        id -> Int8,
+        internal_user_id -> Int8,
+        code -> Text,
     }
 }
 
@@ -34,7 +114,8 @@ table! {
     role (id) {
         id -> Int8,
         name -> Ltree,
-        owner_id -> Nullable<Int8>,
+        owner_id -> Int8,
+        namespace_id -> Int8,
     }
 }
 
@@ -49,6 +130,34 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    session (id) {
+        id -> Int8,
+        internal_user_id -> Int8,
+        token -> Text,
+        created_on -> Timestamptz,
+        last_seen -> Timestamptz,
+        expires_on -> Timestamptz,
+        user_agent -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_ltree::*;
+
+    totp (internal_user_id) {
+        internal_user_id -> Int8,
+        secret -> Text,
+        confirmed_on -> Nullable<Timestamptz>,
+        last_step -> Int8,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use diesel_ltree::*;
@@ -57,6 +166,7 @@ table! {
         id -> Int8,
         name -> Nullable<Text>,
         owner_id -> Int8,
+        namespace_id -> Int8,
     }
 }
 
@@ -82,21 +192,40 @@ table! {
     }
 }
 
+joinable!(api_key -> internal_user (internal_user_id));
+joinable!(email_token -> internal_user (internal_user_id));
+joinable!(namespace -> internal_user (owner_id));
+joinable!(namespace_member -> internal_user (internal_user_id));
+joinable!(namespace_member -> namespace (namespace_id));
 joinable!(permission -> internal_user (owner_id));
+joinable!(permission -> namespace (namespace_id));
+joinable!(recovery_code -> internal_user (internal_user_id));
 joinable!(role -> internal_user (owner_id));
+joinable!(role -> namespace (namespace_id));
 joinable!(role_permission -> permission (permission_id));
 joinable!(role_permission -> role (role_id));
+joinable!(session -> internal_user (internal_user_id));
+joinable!(totp -> internal_user (internal_user_id));
 joinable!(user -> internal_user (owner_id));
+joinable!(user -> namespace (namespace_id));
 joinable!(user_permission -> permission (permission_id));
 joinable!(user_permission -> user (user_id));
 joinable!(user_role -> role (role_id));
 joinable!(user_role -> user (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_key,
+    email_token,
     internal_user,
+    lockout,
+    namespace,
+    namespace_member,
     permission,
+    recovery_code,
     role,
     role_permission,
+    session,
+    totp,
     user,
     user_permission,
     user_role,
@@ -0,0 +1,114 @@
+use diesel::pg::PgConnection;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::database::models::internal_user::{InternalUser, SubmitInternalUser};
+use crate::database::DatabaseConfig;
+use crate::utils::scope::Scope;
+
+// The password the root user used to be created with. Fine for development,
+// refused in release builds.
+pub const DEFAULT_ROOT_PASSWORD: &str = "password";
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RootConfig {
+    pub name: String,
+    pub email: String,
+    pub password: Option<String>,
+    // Alternative to `password`, plays well with docker secrets
+    pub password_file: Option<PathBuf>,
+}
+
+impl Default for RootConfig {
+    fn default() -> RootConfig {
+        RootConfig {
+            name: String::from("root"),
+            email: String::from("root@admin.com"),
+            password: None,
+            password_file: None,
+        }
+    }
+}
+
+pub enum Bootstrap {
+    // The root user, or some other admin, already exists
+    Ready,
+    Created(InternalUser),
+    // No password was configured and there is no admin yet, someone has to
+    // claim the server through the setup token.
+    SetupRequired,
+}
+
+impl RootConfig {
+    // Moves the contents of `password_file` into `password`.
+    pub fn read_password_file(&mut self) -> Result<(), String> {
+        let path = match self.password_file.take() {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        if self.password.is_some() {
+            return Err(String::from(
+                "Only one of root.password and root.password_file can be set",
+            ));
+        }
+        let password = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        self.password = Some(password.trim_end_matches(&['\r', '\n'][..]).to_string());
+        Ok(())
+    }
+
+    pub fn validate(&self, release: bool) -> Result<(), String> {
+        match self.password.as_deref() {
+            Some("") => Err(String::from("The root password cannot be empty")),
+            Some(DEFAULT_ROOT_PASSWORD) if release => Err(String::from(
+                "Refusing to start a release build with the default root password",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Creates the root user if it does not exist yet. An existing root user is
+// left untouched, so changing its password through the API sticks across
+// restarts.
+pub fn bootstrap_root(
+    root: RootConfig,
+    db_config: Arc<DatabaseConfig>,
+    connection: &PgConnection,
+) -> Result<Bootstrap, diesel::result::Error> {
+    if InternalUser::find_by_email(root.email.clone(), connection).is_ok() {
+        return Ok(Bootstrap::Ready);
+    }
+    match root.password {
+        Some(password) => InternalUser::create(
+            SubmitInternalUser {
+                name: root.name,
+                email: root.email,
+                password,
+            },
+            &Scope::ALL,
+            db_config,
+            connection,
+        )
+        .map(Bootstrap::Created),
+        None if InternalUser::admin_exists(connection)? => Ok(Bootstrap::Ready),
+        None => Ok(Bootstrap::SetupRequired),
+    }
+}
+
+// Emails of admins still using the default password, e.g. a root user created
+// before the password was configurable.
+pub fn default_password_admins(
+    connection: &PgConnection,
+) -> Result<Vec<String>, diesel::result::Error> {
+    Ok(InternalUser::all(connection)?
+        .into_iter()
+        .filter(|iuser| {
+            iuser.has_scope(Scope::InternalManage) && iuser.verify_password(DEFAULT_ROOT_PASSWORD)
+        })
+        .map(|iuser| iuser.email)
+        .collect())
+}
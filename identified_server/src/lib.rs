@@ -1,3 +1,6 @@
+// diesel 1.x derives expand to impls inside anonymous consts
+#![allow(non_local_definitions)]
+
 #[macro_use]
 extern crate diesel;
 extern crate diesel_ltree;
@@ -5,6 +8,9 @@ extern crate serde;
 extern crate serde_json;
 
 pub mod api;
+pub mod config;
 pub mod database;
+pub mod mailer;
 pub mod middleware;
+pub mod tls;
 pub mod utils;
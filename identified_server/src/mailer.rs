@@ -0,0 +1,241 @@
+use chrono::{DateTime, Utc};
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SendableEmail, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::config::{MailConfig, MailTransport, SmtpSecurity};
+use crate::utils::common::random_string;
+
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Delivers mail. Sending blocks, like the database calls around it.
+pub trait Mailer: Send + Sync {
+    fn send(&self, message: &Message) -> Result<(), String>;
+}
+
+fn build_email(from: &str, message: &Message) -> Result<SendableEmail, String> {
+    EmailBuilder::new()
+        .from(from)
+        .to(message.to.as_str())
+        .subject(message.subject.as_str())
+        .text(message.body.as_str())
+        .build()
+        .map(|email| email.into())
+        .map_err(|e| format!("Could not build the email to {}: {:?}", message.to, e))
+}
+
+// Only logs the mail, tokens included, for local development.
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        log::info!(
+            "Mail to {}, {}:\n{}",
+            message.to,
+            message.subject,
+            message.body
+        );
+        Ok(())
+    }
+}
+
+// Keeps the mails for tests to read.
+#[cfg(test)]
+impl Mailer for std::sync::Arc<std::sync::Mutex<Vec<Message>>> {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        self.lock().unwrap().push(Message {
+            to: message.to.clone(),
+            subject: message.subject.clone(),
+            body: message.body.clone(),
+        });
+        Ok(())
+    }
+}
+
+// Writes every mail to its own `.eml` file, which mail clients can open.
+pub struct FileMailer {
+    pub directory: PathBuf,
+    pub from: String,
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        let contents = build_email(&self.from, message)?
+            .message_to_string()
+            .map_err(|e| format!("Could not format the email: {}", e))?;
+        let name = format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            random_string(6)
+        );
+        let path = self.directory.join(name);
+        fs::write(&path, contents).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+}
+
+pub struct SmtpMailer {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+}
+
+impl SmtpMailer {
+    // A new connection per mail, they are rare enough.
+    fn client(&self) -> Result<SmtpClient, String> {
+        let tls = || {
+            TlsConnector::new()
+                .map(|connector| ClientTlsParameters::new(self.host.clone(), connector))
+                .map_err(|e| format!("Could not set up TLS for SMTP: {}", e))
+        };
+        let security = match self.security {
+            SmtpSecurity::None => ClientSecurity::None,
+            SmtpSecurity::Starttls => ClientSecurity::Required(tls()?),
+            SmtpSecurity::Tls => ClientSecurity::Wrapper(tls()?),
+        };
+        let client = SmtpClient::new((self.host.as_str(), self.port), security)
+            .map_err(|e| format!("Could not reach {}:{}: {:?}", self.host, self.port, e))?;
+        Ok(match &self.credentials {
+            Some((username, password)) => {
+                client.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            None => client,
+        })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, message: &Message) -> Result<(), String> {
+        let email = build_email(&self.from, message)?;
+        let mut transport = self.client()?.transport();
+        let result = transport.send(email);
+        transport.close();
+        result
+            .map(|_| ())
+            .map_err(|e| format!("Could not send the email to {}: {:?}", message.to, e))
+    }
+}
+
+// Writes the mails the server sends and hands them to the configured mailer.
+pub struct Outbox {
+    mailer: Box<dyn Mailer>,
+    password_reset_url: Option<String>,
+    email_verification_url: Option<String>,
+}
+
+// Puts the token in the configured url, or leaves it on its own line.
+fn link(template: &Option<String>, token: &str) -> String {
+    match template {
+        Some(template) => template.replace("{token}", token),
+        None => token.to_string(),
+    }
+}
+
+impl Outbox {
+    pub fn new(mailer: Box<dyn Mailer>, config: &MailConfig) -> Outbox {
+        Outbox {
+            mailer,
+            password_reset_url: config.password_reset_url.clone(),
+            email_verification_url: config.email_verification_url.clone(),
+        }
+    }
+
+    pub fn load(config: &MailConfig) -> Outbox {
+        let mailer: Box<dyn Mailer> = match config.transport {
+            MailTransport::Log => Box::new(LogMailer),
+            MailTransport::File => Box::new(FileMailer {
+                directory: config.directory.clone().unwrap_or_default(),
+                from: config.from.clone(),
+            }),
+            MailTransport::Smtp => Box::new(SmtpMailer {
+                host: config.smtp_host.clone().unwrap_or_default(),
+                port: config.smtp_port,
+                security: config.smtp_security,
+                credentials: config
+                    .smtp_username
+                    .clone()
+                    .zip(config.smtp_password.clone()),
+                from: config.from.clone(),
+            }),
+        };
+        Outbox::new(mailer, config)
+    }
+
+    pub fn password_reset(
+        &self,
+        to: &str,
+        token: &str,
+        expires_on: DateTime<Utc>,
+    ) -> Result<(), String> {
+        self.mailer.send(&Message {
+            to: to.to_string(),
+            subject: String::from("Reset your password"),
+            body: format!(
+                "Someone asked to reset the password of your account. Use this to choose a new one before {}:\n\n{}\n\nIf that was not you, you can ignore this email.\n",
+                expires_on.format("%Y-%m-%d %H:%M UTC"),
+                link(&self.password_reset_url, token)
+            ),
+        })
+    }
+
+    pub fn email_verification(
+        &self,
+        to: &str,
+        token: &str,
+        expires_on: DateTime<Utc>,
+    ) -> Result<(), String> {
+        self.mailer.send(&Message {
+            to: to.to_string(),
+            subject: String::from("Verify your email"),
+            body: format!(
+                "Use this to verify your email before {}:\n\n{}\n",
+                expires_on.format("%Y-%m-%d %H:%M UTC"),
+                link(&self.email_verification_url, token)
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_put_in_the_url() {
+        let template = Some(String::from("https://example.com/reset?token={token}"));
+        assert_eq!(
+            link(&template, "abc"),
+            "https://example.com/reset?token=abc"
+        );
+        assert_eq!(link(&None, "abc"), "abc");
+    }
+
+    #[test]
+    fn file_mailer_writes_one_file_per_mail() {
+        let directory = std::env::temp_dir().join(format!("identified-mail-{}", random_string(8)));
+        fs::create_dir_all(&directory).unwrap();
+        let config = MailConfig {
+            transport: MailTransport::File,
+            directory: Some(directory.clone()),
+            ..MailConfig::default()
+        };
+        let outbox = Outbox::load(&config);
+        outbox
+            .password_reset("root@admin.com", "secret-token", Utc::now())
+            .unwrap();
+        let files: Vec<_> = fs::read_dir(&directory).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let contents = fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(contents.contains("To: <root@admin.com>"), "{}", contents);
+        assert!(contents.contains("secret-token"), "{}", contents);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
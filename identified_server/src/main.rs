@@ -1,16 +1,19 @@
-use identified_server::database::models::internal_user::SubmitInternalUser;
 use identified_server::{
     api::root::filters::main_filter,
-    database::models::internal_user::InternalUser,
-    database::{establish_connection, get_connection, DatabaseConfig},
-    utils::common::Session,
+    config::Config,
+    database::seed::{bootstrap_root, default_password_admins, Bootstrap},
+    database::{establish_connection, get_connection},
+    mailer::Outbox,
+    tls::{self, Certificates},
+    utils::common::{random_string, Session},
 };
 use listenfd::ListenFd;
-use ring::rand::*;
+use log::{error, info, warn};
 use std::collections::HashMap;
-use std::num::NonZeroU32;
 use std::sync::Arc;
 use std::sync::Mutex;
+use tokio::net::TcpListener;
+use warp::Filter;
 
 #[tokio::main]
 async fn main() {
@@ -18,62 +21,99 @@ async fn main() {
     // let args: Vec<String> = std::env::args().collect();
     // let is_debug = !args.iter().any(|arg| arg.eq("--release"));
 
-    // Start the server and add routes
-    let db_pool = establish_connection();
-    let db_config = Arc::new(DatabaseConfig {
-        iterations: NonZeroU32::new(1000).unwrap(),
-        rng: SystemRandom::new(),
-        salt_length: 24,
-        api_key_length: 30,
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("Invalid configuration:\n{}", e);
+        std::process::exit(1);
     });
+    pretty_env_logger::formatted_builder()
+        .parse_filters(&config.logging.level)
+        .init();
+
+    // Start the server and add routes
+    let db_pool = establish_connection(&config.database);
+    let db_config = Arc::new(config.database_config().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    }));
 
     let session = Arc::new(Session {
         connection_pool: db_pool,
     });
 
-    // Either create a new root user or update the password of the root user
-    // TODO: The email and password fields of the user should be externally
-    //          configurable
-    //
     // Should cause internal error if a connection could not be acquired.
     let connection = get_connection(session.clone()).unwrap();
-    let possible = InternalUser::find_by_email(String::from("root@admin.com"), &connection);
-    let _user = match possible {
-        // Update existing
-        Ok(u) => InternalUser::update_auth_token(u.id, db_config.clone(), &connection)
-            .expect("Failure to update root user's password"),
-        Err(_) => InternalUser::create(
-            SubmitInternalUser {
-                name: "root".to_string(),
-                email: "root@admin.com".to_string(),
-                password: "password".to_string(),
-            },
-            true,
-            db_config.clone(),
-            &connection,
-        )
-        .expect("Failure to create root user"),
+    let setup_token = match bootstrap_root(config.root.clone(), db_config.clone(), &connection)
+        .expect("Failure to create root user")
+    {
+        Bootstrap::Ready => None,
+        Bootstrap::Created(iuser) => {
+            info!("Created root user {}", iuser.email);
+            None
+        }
+        Bootstrap::SetupRequired => {
+            let token = random_string(db_config.api_key_length);
+            // Printed once instead of logged, so the token does not end up
+            // wherever the logs are shipped to
+            warn!("No admin exists yet, the setup token was printed to stderr");
+            eprintln!(
+                "No admin exists yet, POST /setup with this token to create one: {}",
+                token
+            );
+            Some(token)
+        }
     };
+    if config.server.release {
+        let defaults = default_password_admins(&connection).expect("Failure to check admins");
+        if !defaults.is_empty() {
+            error!(
+                "Refusing to start a release build, these admins still use the default password: {}",
+                defaults.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+    drop(connection);
 
-    // If the program was not built using release, try and use listenfd for
-    // hot-reloading
-    let server = warp::serve(main_filter(
+    let certificates = match config.tls.is_enabled() {
+        true => Some(Certificates::load(config.tls.clone()).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        })),
+        false => None,
+    };
+    let filter = main_filter(
         db_config,
         session,
         Arc::new(Mutex::new(HashMap::new())),
-    ));
-    if let Ok(profile) = std::env::var("PROFILE") {
-        if let "release" = profile.as_str() {
-            server.run(([127, 0, 0, 1], 3000)).await;
-            return;
-        }
-    }
+        Arc::new(tokio::sync::Mutex::new(setup_token)),
+        Arc::new(Outbox::load(&config.mail)),
+    )
+    .with(warp::log("identified_server::api"));
 
-    let mut listenfd = ListenFd::from_env();
-    if let Some(l) = listenfd.take_tcp_listener(0).unwrap() {
-        let mut listener = tokio::net::TcpListener::from_std(l).unwrap();
-        server.run_incoming(listener.incoming()).await;
-    } else {
-        server.run(([127, 0, 0, 1], 3000)).await;
+    // Outside of release mode, try and use listenfd for hot-reloading
+    let handed_over = match config.server.release {
+        true => None,
+        false => ListenFd::from_env().take_tcp_listener(0).unwrap(),
     };
+    let handed_over = handed_over.map(|l| {
+        info!("Listening on the socket handed over by listenfd");
+        TcpListener::from_std(l).unwrap()
+    });
+    if handed_over.is_none() {
+        info!("Listening on {}", config.listen_address());
+    }
+    match (certificates, handed_over) {
+        (Some(certificates), Some(listener)) => tls::serve(listener, filter, certificates).await,
+        (Some(certificates), None) => {
+            let listener = TcpListener::bind(config.listen_address())
+                .await
+                .unwrap_or_else(|e| {
+                    error!("Could not listen on {}: {}", config.listen_address(), e);
+                    std::process::exit(1);
+                });
+            tls::serve(listener, filter, certificates).await
+        }
+        (None, Some(mut listener)) => warp::serve(filter).run_incoming(listener.incoming()).await,
+        (None, None) => warp::serve(filter).run(config.listen_address()).await,
+    }
 }
@@ -0,0 +1,304 @@
+use openssl::x509::X509;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_rustls::rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use tokio_rustls::rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth,
+    RootCertStore, ServerConfig, Session,
+};
+use tokio_rustls::TlsAcceptor;
+use warp::hyper::server::conn::Http;
+use warp::hyper::service::{service_fn, Service};
+use warp::{Filter, Reply};
+
+use crate::config::{Config, TlsConfig};
+
+// Added to every request received over TLS. warp only knows the peer address
+// of connections it accepted itself, so it is passed along here instead.
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    pub remote_addr: SocketAddr,
+    pub client_subject: Option<String>,
+    // Email of the internal user `client_subject` is mapped to
+    pub client_user: Option<String>,
+}
+
+// The server configuration currently handed to new connections along with
+// the client users, both replaced when the certificates are reloaded.
+#[derive(Clone)]
+pub struct Certificates {
+    current: Arc<RwLock<Current>>,
+}
+
+struct Current {
+    server_config: Arc<ServerConfig>,
+    client_users: Arc<HashMap<String, String>>,
+}
+
+impl Current {
+    fn load(tls: TlsConfig) -> Result<Current, String> {
+        Ok(Current {
+            server_config: Arc::new(server_config(&tls)?),
+            client_users: Arc::new(tls.client_users),
+        })
+    }
+}
+
+impl Certificates {
+    pub fn load(tls: TlsConfig) -> Result<Certificates, String> {
+        Ok(Certificates {
+            current: Arc::new(RwLock::new(Current::load(tls)?)),
+        })
+    }
+
+    // Rereads the configuration, so changed paths and client users are
+    // picked up as well.
+    pub fn reload(&self) -> Result<(), String> {
+        self.replace(Config::load()?.tls)
+    }
+
+    // Connections that are already open keep using the old certificates.
+    // Nothing is replaced unless the whole configuration loads.
+    fn replace(&self, tls: TlsConfig) -> Result<(), String> {
+        let current = Current::load(tls)?;
+        *self.current.write().unwrap() = current;
+        Ok(())
+    }
+
+    // Read together, so a reload cannot pair the certificates of one
+    // configuration with the client users of another.
+    fn acceptor(&self) -> (TlsAcceptor, Arc<HashMap<String, String>>) {
+        let current = self.current.read().unwrap();
+        (
+            TlsAcceptor::from(current.server_config.clone()),
+            current.client_users.clone(),
+        )
+    }
+}
+
+fn read_certs(path: &Path) -> Result<Vec<tokio_rustls::rustls::Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    match certs(&mut BufReader::new(file)) {
+        Ok(certs) if !certs.is_empty() => Ok(certs),
+        _ => Err(format!("{} contains no certificates", path.display())),
+    }
+}
+
+fn read_key(path: &Path) -> Result<tokio_rustls::rustls::PrivateKey, String> {
+    let open = || {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))
+    };
+    let mut keys = pkcs8_private_keys(&mut open()?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open()?).unwrap_or_default();
+    }
+    keys.pop()
+        .ok_or_else(|| format!("{} contains no private key", path.display()))
+}
+
+fn server_config(tls: &TlsConfig) -> Result<ServerConfig, String> {
+    let (cert_path, key_path) = match (&tls.cert_path, &tls.key_path) {
+        (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+        _ => return Err(String::from("tls.cert_path and tls.key_path must be set")),
+    };
+    let verifier = match &tls.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(&cert)
+                    .map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+            }
+            match tls.require_client_cert {
+                true => AllowAnyAuthenticatedClient::new(roots),
+                false => AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+            }
+        }
+        None => NoClientAuth::new(),
+    };
+    let mut server_config = ServerConfig::new(verifier);
+    server_config
+        .set_single_cert(read_certs(cert_path)?, read_key(key_path)?)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+    server_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    Ok(server_config)
+}
+
+// Formats the subject like `openssl x509 -noout -subject -nameopt RFC2253`,
+// most specific attribute first, e.g. `CN=billing,O=Example`.
+pub fn certificate_subject(der: &[u8]) -> Option<String> {
+    let cert = X509::from_der(der).ok()?;
+    let mut attributes = Vec::new();
+    for entry in cert.subject_name().entries() {
+        let name = entry.object().nid().short_name().ok()?;
+        let value = entry.data().as_utf8().ok()?;
+        attributes.push(format!("{}={}", name, escape_attribute(&value)));
+    }
+    attributes.reverse();
+    Some(attributes.join(","))
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = i == value.chars().count() - 1 && c == ' ';
+        if leading || trailing || ",+\"\\<>;".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Reloads the certificates and client users whenever the process receives
+// SIGHUP. A broken certificate or configuration is logged and the previous
+// one kept.
+pub async fn reload_on_hangup(certificates: Certificates) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            log::error!(
+                "Could not listen for SIGHUP, certificates will not reload: {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        match certificates.reload() {
+            Ok(()) => log::info!("Reloaded TLS certificates and client users"),
+            Err(e) => log::error!("Keeping the previous TLS certificates: {}", e),
+        }
+    }
+}
+
+pub async fn serve<F>(mut listener: TcpListener, filter: F, certificates: Certificates)
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    tokio::spawn(reload_on_hangup(certificates.clone()));
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                log::warn!("Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let (acceptor, client_users) = certificates.acceptor();
+        let service = warp::service(filter.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                    return;
+                }
+            };
+            let client_subject = stream
+                .get_ref()
+                .1
+                .get_peer_certificates()
+                .and_then(|chain| chain.first().and_then(|cert| certificate_subject(&cert.0)));
+            let info = ConnectionInfo {
+                remote_addr,
+                client_user: client_subject
+                    .as_deref()
+                    .and_then(|subject| client_users.get(subject).cloned()),
+                client_subject,
+            };
+            let service = service_fn(move |mut request| {
+                request.extensions_mut().insert(info.clone());
+                service.clone().call(request)
+            });
+            if let Err(e) = Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                log::debug!("Connection with {} failed: {}", remote_addr, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+
+    fn self_signed(key: &PKey<openssl::pkey::Private>) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("O", "Example, Inc").unwrap();
+        name.append_entry_by_text("CN", "billing").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(key, MessageDigest::sha256()).unwrap();
+        cert.build()
+    }
+
+    #[test]
+    fn subject_is_formatted_most_specific_first() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let der = self_signed(&key).to_der().unwrap();
+        assert_eq!(
+            certificate_subject(&der).as_deref(),
+            Some("CN=billing,O=Example\\, Inc")
+        );
+    }
+
+    #[test]
+    fn reloading_replaces_client_users() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let dir = std::env::temp_dir().join(format!("identified-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, self_signed(&key).to_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        let tls = |email: &str| TlsConfig {
+            cert_path: Some(cert_path.clone()),
+            key_path: Some(key_path.clone()),
+            client_users: vec![(String::from("CN=billing"), String::from(email))]
+                .into_iter()
+                .collect(),
+            ..TlsConfig::default()
+        };
+        let user =
+            |certificates: &Certificates| certificates.acceptor().1.get("CN=billing").cloned();
+
+        let certificates = Certificates::load(tls("old@example.com")).unwrap();
+        assert_eq!(user(&certificates).as_deref(), Some("old@example.com"));
+        certificates.replace(tls("new@example.com")).unwrap();
+        assert_eq!(user(&certificates).as_deref(), Some("new@example.com"));
+
+        let broken = TlsConfig {
+            key_path: Some(dir.join("missing.pem")),
+            ..tls("broken@example.com")
+        };
+        assert!(certificates.replace(broken).is_err());
+        assert_eq!(user(&certificates).as_deref(), Some("new@example.com"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,2 +1,5 @@
 pub mod common;
 pub mod errors;
+pub mod password;
+pub mod scope;
+pub mod totp;
@@ -1,17 +1,16 @@
-use crate::database::models::internal_user::InternalUser;
+use crate::database::models::session::SessionOrigin;
 use crate::database::{DatabaseConfig, PgPool};
+use crate::tls::ConnectionInfo;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
-use ring::pbkdf2::PBKDF2_HMAC_SHA512;
-use ring::{digest, pbkdf2};
+use ring::hmac;
 use serde::{Deserialize, Serialize};
-use std::num::NonZeroU32;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use warp::Rejection;
 use warp::{self, Filter};
+use warp::{reject, Rejection};
 
-const CREDENTIAL_LEN: usize = digest::SHA512_OUTPUT_LEN;
-pub type Credential = [u8; CREDENTIAL_LEN];
+use crate::utils::errors::{InputError, ValidationError};
 
 #[derive(Clone)]
 pub struct Session {
@@ -25,19 +24,63 @@ pub struct WithId<T> {
     pub contained: T,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct SubmitIds {
+    pub ids: Vec<i64>,
+}
+
+// Ids given as `?ids=1,2,3`, for DELETE requests which should not need a body.
+#[derive(Deserialize)]
+pub struct IdsQuery {
+    #[serde(deserialize_with = "comma_separated")]
+    pub ids: Vec<i64>,
+}
+
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<i64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let ids = String::deserialize(deserializer)?;
+    ids.split(',')
+        .filter(|id| !id.is_empty())
+        .map(|id| id.trim().parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// Tells a field that is `null` apart from one that is left out, which then
+// needs `#[serde(default)]`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteOptions {
+    #[serde(default)]
+    pub cascade: bool,
+}
+
+// A session whose every checkout fails, for testing filters that have to
+// refuse before reaching the database.
+#[cfg(test)]
+pub fn offline_session() -> Arc<Session> {
+    let manager = diesel::r2d2::ConnectionManager::new("postgres://localhost:1/offline");
+    Arc::new(Session {
+        connection_pool: diesel::r2d2::Pool::builder()
+            .min_idle(Some(0))
+            .build_unchecked(manager),
+    })
+}
+
 pub fn with<T: Send + Sync>(
     item: Arc<T>,
 ) -> impl Filter<Extract = (Arc<T>,), Error = std::convert::Infallible> + Clone {
     warp::any().map(move || item.clone())
 }
 
-pub fn with_predicate<T>(
-    predicate: fn(T) -> Result<T, Rejection>,
-) -> impl Filter<Extract = (fn(T) -> Result<T, Rejection>,), Error = std::convert::Infallible> + Clone
-{
-    warp::any().map(move || predicate)
-}
-
 pub fn toss<T>(
     filter: impl Filter<Extract = (T,), Error = Rejection> + Clone,
 ) -> impl Filter<Extract = (), Error = Rejection> + Clone {
@@ -50,18 +93,170 @@ pub fn with_db_config(
     warp::any().map(move || db_config.clone())
 }
 
+// The user agent and peer address of a request, recorded on login.
+pub fn with_origin() -> impl Filter<Extract = (SessionOrigin,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("user-agent")
+        .and(warp::addr::remote())
+        .and(warp::ext::optional::<ConnectionInfo>())
+        .map(
+            |user_agent, remote: Option<SocketAddr>, info: Option<ConnectionInfo>| SessionOrigin {
+                user_agent,
+                ip_address: remote
+                    .or_else(|| info.map(|info| info.remote_addr))
+                    .map(|addr| addr.ip().to_string()),
+            },
+        )
+}
+
+pub fn distinct(ids: &[i64]) -> Vec<i64> {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
 pub fn random_string(n: usize) -> String {
     thread_rng().sample_iter(&Alphanumeric).take(n).collect()
 }
 
-pub fn hash_password(password: String, salt: String, iterations: NonZeroU32) -> Credential {
-    let mut result: Credential = [0u8; CREDENTIAL_LEN];
-    pbkdf2::derive(
-        PBKDF2_HMAC_SHA512,
-        iterations,
-        salt.as_bytes(),
-        password.as_bytes(),
-        &mut result,
-    );
-    result
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Whether an `If-Match` header accepts the current entity tag. Weak tags
+// never match, as the comparison has to be strong.
+pub fn if_match(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate == etag)
+}
+
+pub fn hash_token(key: &hmac::Key, token: &str) -> String {
+    to_hex(hmac::sign(key, token.as_bytes()).as_ref())
+}
+
+// Labels may only contain alphanumerics and underscores, see
+// https://www.postgresql.org/docs/current/ltree.html
+pub fn is_valid_ltree(path: &str) -> bool {
+    path.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 256
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+// Every prefix of the path, including the path itself, e.g. `a.b` yields
+// `a` and `a.b`.
+pub fn ltree_ancestors(path: &str) -> Vec<String> {
+    let labels: Vec<&str> = path.split('.').collect();
+    (1..=labels.len()).map(|n| labels[..n].join(".")).collect()
+}
+
+pub fn validate_ltree(field: &str, path: &str) -> Result<(), Rejection> {
+    match is_valid_ltree(path) {
+        true => Ok(()),
+        false => Err(reject::custom(InputError::new(
+            field,
+            ValidationError::Invalid,
+        ))),
+    }
+}
+
+// The last label of the path, e.g. `backend` for `eng.backend`.
+pub fn ltree_label(path: &str) -> &str {
+    path.rsplit('.').next().unwrap_or(path)
+}
+
+pub fn ltree_depth(path: &str) -> i32 {
+    path.split('.').count() as i32
+}
+
+pub fn is_ltree_descendant(path: &str, of: &str) -> bool {
+    path == of || path.starts_with(&format!("{}.", of))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn ids_are_read_from_the_query() {
+        let filter = warp::query::<IdsQuery>();
+        let query = warp::test::request()
+            .path("/?ids=3,1,2")
+            .filter(&filter)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(query.ids, vec![3, 1, 2]);
+        let query = warp::test::request()
+            .path("/?ids=")
+            .filter(&filter)
+            .await
+            .ok()
+            .unwrap();
+        assert!(query.ids.is_empty());
+        assert!(warp::test::request()
+            .path("/?ids=1,a")
+            .filter(&filter)
+            .await
+            .is_err());
+        assert!(warp::test::request()
+            .path("/")
+            .filter(&filter)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn tokens_are_hashed_with_the_key() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"secret");
+        let hashed = hash_token(&key, "token");
+        assert_eq!(hashed.len(), 64);
+        assert!(hashed.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(hashed, hash_token(&key, "token"));
+        assert_ne!(hashed, hash_token(&key, "token2"));
+        let other = hmac::Key::new(hmac::HMAC_SHA256, b"other");
+        assert_ne!(hashed, hash_token(&other, "token"));
+    }
+
+    #[test]
+    fn ltree_paths_are_validated() {
+        assert!(is_valid_ltree("billing"));
+        assert!(is_valid_ltree("billing.invoices_2021.read"));
+        assert!(is_valid_ltree(&"a".repeat(256)));
+        assert!(!is_valid_ltree(""));
+        assert!(!is_valid_ltree("billing."));
+        assert!(!is_valid_ltree(".billing"));
+        assert!(!is_valid_ltree("billing..read"));
+        assert!(!is_valid_ltree("billing-read"));
+        assert!(!is_valid_ltree("billing read"));
+        assert!(!is_valid_ltree("fakturering.läsa"));
+        assert!(!is_valid_ltree(&"a".repeat(257)));
+    }
+
+    #[test]
+    fn ltree_ancestors_include_the_path() {
+        assert_eq!(ltree_ancestors("a"), vec!["a"]);
+        assert_eq!(ltree_ancestors("a.b.c"), vec!["a", "a.b", "a.b.c"]);
+    }
+
+    #[test]
+    fn ltree_label_and_depth() {
+        assert_eq!(ltree_label("eng.backend"), "backend");
+        assert_eq!(ltree_label("eng"), "eng");
+        assert_eq!(ltree_depth("eng"), 1);
+        assert_eq!(ltree_depth("eng.backend.api"), 3);
+    }
+
+    #[test]
+    fn ltree_descendants_match_whole_labels() {
+        assert!(is_ltree_descendant("eng", "eng"));
+        assert!(is_ltree_descendant("eng.backend", "eng"));
+        assert!(is_ltree_descendant("eng.backend.api", "eng"));
+        assert!(!is_ltree_descendant("engineering", "eng"));
+        assert!(!is_ltree_descendant("eng", "eng.backend"));
+        assert!(!is_ltree_descendant("staff.eng", "eng"));
+    }
 }
@@ -1,5 +1,8 @@
+use diesel::result::DatabaseErrorKind;
+use http::StatusCode;
 use serde::Serialize;
-use warp::reject;
+use std::convert::Infallible;
+use warp::{reject, Rejection, Reply};
 
 impl reject::Reject for Error {}
 impl reject::Reject for DbError {}
@@ -9,10 +12,119 @@ impl reject::Reject for AuthenticationError {}
 impl reject::Reject for AuthorizationError {}
 impl reject::Reject for ServerError {}
 
+pub fn query_rejection(err: diesel::result::Error) -> Rejection {
+    match err {
+        diesel::result::Error::NotFound => reject::custom(DbError::NotFound(format!("{}", err))),
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, ref info) => {
+            reject::custom(InputError {
+                field: (
+                    info.constraint_name().unwrap_or_default().to_string(),
+                    ValidationError::AlreadyExists,
+                ),
+            })
+        }
+        // Rows that still refer to what was deleted
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, ref info) => {
+            reject::custom(InputError {
+                field: (
+                    info.constraint_name().unwrap_or_default().to_string(),
+                    ValidationError::InUse,
+                ),
+            })
+        }
+        _ => reject::custom(DbError::DatabaseQueryError(format!("{}", err))),
+    }
+}
+
+// The body of every error response. `error` is a stable identifier clients can
+// match on, `message` is meant for humans and may change.
 #[derive(Serialize)]
-struct ErrorMessage {
-    code: u16,
-    message: String,
+pub struct ErrorMessage {
+    pub code: u16,
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl ErrorMessage {
+    fn new(status: StatusCode, error: &'static str, message: String) -> ErrorMessage {
+        ErrorMessage {
+            code: status.as_u16(),
+            error,
+            message,
+        }
+    }
+
+    pub fn from_rejection(err: &Rejection) -> ErrorMessage {
+        if let Some(e) = err.find::<Error>() {
+            e.to_message()
+        } else if let Some(e) = err.find::<AuthorizationError>() {
+            e.to_message()
+        } else if let Some(e) = err.find::<AuthenticationError>() {
+            e.to_message()
+        } else if let Some(e) = err.find::<InputError>() {
+            e.to_message()
+        } else if let Some(e) = err.find::<ValidationError>() {
+            e.to_message(String::from("request"))
+        } else if let Some(e) = err.find::<DbError>() {
+            e.to_message()
+        } else if let Some(e) = err.find::<ServerError>() {
+            e.to_message()
+        } else if err.is_not_found() {
+            ErrorMessage::new(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                String::from("No route matches the request"),
+            )
+        } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+            ErrorMessage::new(StatusCode::BAD_REQUEST, "invalid_body", format!("{}", e))
+        } else if let Some(e) = err.find::<reject::InvalidQuery>() {
+            ErrorMessage::new(StatusCode::BAD_REQUEST, "invalid_query", format!("{}", e))
+        } else if let Some(e) = err.find::<reject::MissingHeader>() {
+            ErrorMessage::new(StatusCode::BAD_REQUEST, "missing_header", format!("{}", e))
+        } else if let Some(e) = err.find::<reject::InvalidHeader>() {
+            ErrorMessage::new(StatusCode::BAD_REQUEST, "invalid_header", format!("{}", e))
+        } else if let Some(e) = err.find::<reject::LengthRequired>() {
+            ErrorMessage::new(
+                StatusCode::LENGTH_REQUIRED,
+                "length_required",
+                format!("{}", e),
+            )
+        } else if let Some(e) = err.find::<reject::PayloadTooLarge>() {
+            ErrorMessage::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                format!("{}", e),
+            )
+        } else if let Some(e) = err.find::<reject::UnsupportedMediaType>() {
+            ErrorMessage::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                format!("{}", e),
+            )
+        } else if let Some(e) = err.find::<reject::MethodNotAllowed>() {
+            ErrorMessage::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                format!("{}", e),
+            )
+        } else {
+            log::error!("Unhandled rejection: {:?}", err);
+            ErrorMessage::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal_error",
+                String::from("Internal server error"),
+            )
+        }
+    }
+}
+
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let message = ErrorMessage::from_rejection(&err);
+    let status = StatusCode::from_u16(message.code).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&message),
+        status,
+    ))
 }
 
 #[derive(Serialize, Debug)]
@@ -27,18 +139,24 @@ pub enum Error {
 #[derive(Serialize, Debug)]
 pub enum ServerError {
     SerializationError(String),
+    MailError(String),
 }
 
 #[derive(Serialize, Debug)]
 pub enum DbError {
     DatabaseConnectionError(String),
     DatabaseQueryError(String),
+    NotFound(String),
 }
 
 #[derive(Serialize, Debug)]
 pub enum ValidationError {
     Required,
     AlreadyExists,
+    Invalid,
+    InUse,
+    // Changed since the client last read it
+    Outdated,
 }
 
 #[derive(Serialize, Debug)]
@@ -46,10 +164,23 @@ pub struct InputError {
     pub field: (String, ValidationError),
 }
 
+impl InputError {
+    pub fn new(field: &str, error: ValidationError) -> InputError {
+        InputError {
+            field: (field.to_string(), error),
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub enum AuthenticationError {
-    InvalidEmail,
-    InvalidPassword,
+    // The same for an unknown email and a wrong password, so logins cannot
+    // be used to find out which emails exist
+    InvalidCredentials,
+    // Seconds until logging in is allowed again
+    TooManyAttempts(i64),
+    InvalidChallenge,
+    InvalidCode,
     CouldNotGenerateAuthToken,
     InvalidLogin(String),
 }
@@ -60,4 +191,303 @@ pub enum AuthorizationError {
     ExpiredToken,
     InvalidToken,
     NoToken,
+    UnknownCertificate,
+    TwoFactorRequired,
+}
+
+impl Error {
+    fn to_message(&self) -> ErrorMessage {
+        match self {
+            Error::Server(e) => e.to_message(),
+            Error::Db(e) => e.to_message(),
+            Error::Input(e) => e.to_message(),
+            Error::Authentication(e) => e.to_message(),
+            Error::Authorization(e) => e.to_message(),
+        }
+    }
+}
+
+impl ServerError {
+    fn to_message(&self) -> ErrorMessage {
+        match self {
+            ServerError::SerializationError(e) => {
+                log::error!("Serialization error: {}", e);
+                ErrorMessage::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "serialization_error",
+                    String::from("Could not serialize the response"),
+                )
+            }
+            ServerError::MailError(e) => {
+                log::error!("Mail error: {}", e);
+                ErrorMessage::new(
+                    StatusCode::BAD_GATEWAY,
+                    "mail_error",
+                    String::from("The email could not be sent"),
+                )
+            }
+        }
+    }
+}
+
+impl DbError {
+    fn to_message(&self) -> ErrorMessage {
+        match self {
+            DbError::DatabaseConnectionError(e) => {
+                log::error!("Database connection error: {}", e);
+                ErrorMessage::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "database_unavailable",
+                    String::from("The database is currently unavailable"),
+                )
+            }
+            DbError::DatabaseQueryError(e) => {
+                log::error!("Database query error: {}", e);
+                ErrorMessage::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database_error",
+                    String::from("The database query failed"),
+                )
+            }
+            DbError::NotFound(_) => ErrorMessage::new(
+                StatusCode::NOT_FOUND,
+                "not_found",
+                String::from("The requested resource does not exist"),
+            ),
+        }
+    }
+}
+
+impl ValidationError {
+    fn to_message(&self, field: String) -> ErrorMessage {
+        match self {
+            ValidationError::Required => ErrorMessage::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "required",
+                format!("`{}` is required", field),
+            ),
+            ValidationError::AlreadyExists => ErrorMessage::new(
+                StatusCode::CONFLICT,
+                "already_exists",
+                format!("`{}` already exists", field),
+            ),
+            ValidationError::Invalid => ErrorMessage::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid",
+                format!("`{}` is invalid", field),
+            ),
+            ValidationError::InUse => ErrorMessage::new(
+                StatusCode::CONFLICT,
+                "in_use",
+                format!("`{}` is still in use", field),
+            ),
+            ValidationError::Outdated => ErrorMessage::new(
+                StatusCode::PRECONDITION_FAILED,
+                "precondition_failed",
+                format!("`{}` does not match the current version", field),
+            ),
+        }
+    }
+}
+
+impl InputError {
+    fn to_message(&self) -> ErrorMessage {
+        let (field, error) = &self.field;
+        error.to_message(field.clone())
+    }
+}
+
+impl AuthenticationError {
+    fn to_message(&self) -> ErrorMessage {
+        match self {
+            AuthenticationError::InvalidCredentials => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+                String::from("The email or password is incorrect"),
+            ),
+            AuthenticationError::TooManyAttempts(seconds) => ErrorMessage::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_attempts",
+                format!("Too many attempts, try again in {} seconds", seconds),
+            ),
+            AuthenticationError::InvalidChallenge => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_challenge",
+                String::from("The login challenge is invalid or has expired"),
+            ),
+            AuthenticationError::InvalidCode => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_code",
+                String::from("The two-factor code is incorrect"),
+            ),
+            AuthenticationError::CouldNotGenerateAuthToken => ErrorMessage::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "token_generation_failed",
+                String::from("Could not generate an authorization token"),
+            ),
+            AuthenticationError::InvalidLogin(e) => {
+                ErrorMessage::new(StatusCode::UNAUTHORIZED, "invalid_login", e.clone())
+            }
+        }
+    }
+}
+
+impl AuthorizationError {
+    fn to_message(&self) -> ErrorMessage {
+        match self {
+            AuthorizationError::Unauthorized => ErrorMessage::new(
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                String::from("Not allowed to perform this action"),
+            ),
+            AuthorizationError::ExpiredToken => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "expired_token",
+                String::from("The authorization token has expired"),
+            ),
+            AuthorizationError::InvalidToken => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_token",
+                String::from("The authorization token is invalid"),
+            ),
+            AuthorizationError::NoToken => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "no_token",
+                String::from("No authorization token was provided"),
+            ),
+            AuthorizationError::UnknownCertificate => ErrorMessage::new(
+                StatusCode::UNAUTHORIZED,
+                "unknown_certificate",
+                String::from("The client certificate is not mapped to a user"),
+            ),
+            AuthorizationError::TwoFactorRequired => ErrorMessage::new(
+                StatusCode::FORBIDDEN,
+                "two_factor_required",
+                String::from("Two-factor authentication has to be set up first"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::Error::DatabaseError;
+    use warp::Filter;
+
+    fn database_error(kind: DatabaseErrorKind) -> diesel::result::Error {
+        DatabaseError(kind, Box::new(String::from("violation")))
+    }
+
+    #[test]
+    fn rejections_map_to_status_and_code() {
+        let cases: Vec<(Rejection, StatusCode, &str)> = vec![
+            (
+                reject::custom(InputError::new("if-match", ValidationError::Outdated)),
+                StatusCode::PRECONDITION_FAILED,
+                "precondition_failed",
+            ),
+            (
+                reject::custom(InputError::new("email", ValidationError::AlreadyExists)),
+                StatusCode::CONFLICT,
+                "already_exists",
+            ),
+            (
+                reject::custom(InputError::new("id", ValidationError::InUse)),
+                StatusCode::CONFLICT,
+                "in_use",
+            ),
+            (
+                reject::custom(InputError::new("name", ValidationError::Required)),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "required",
+            ),
+            (
+                reject::custom(ValidationError::Invalid),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid",
+            ),
+            (
+                reject::custom(AuthenticationError::InvalidCredentials),
+                StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+            ),
+            (
+                reject::custom(AuthenticationError::TooManyAttempts(30)),
+                StatusCode::TOO_MANY_REQUESTS,
+                "too_many_attempts",
+            ),
+            (
+                reject::custom(AuthorizationError::Unauthorized),
+                StatusCode::FORBIDDEN,
+                "forbidden",
+            ),
+            (
+                reject::custom(AuthorizationError::ExpiredToken),
+                StatusCode::UNAUTHORIZED,
+                "expired_token",
+            ),
+            (
+                reject::custom(Error::Authorization(AuthorizationError::NoToken)),
+                StatusCode::UNAUTHORIZED,
+                "no_token",
+            ),
+            (
+                reject::custom(DbError::DatabaseConnectionError(String::new())),
+                StatusCode::SERVICE_UNAVAILABLE,
+                "database_unavailable",
+            ),
+            (
+                reject::custom(ServerError::MailError(String::new())),
+                StatusCode::BAD_GATEWAY,
+                "mail_error",
+            ),
+            (
+                query_rejection(diesel::result::Error::NotFound),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            (
+                query_rejection(database_error(DatabaseErrorKind::UniqueViolation)),
+                StatusCode::CONFLICT,
+                "already_exists",
+            ),
+            (
+                query_rejection(database_error(DatabaseErrorKind::ForeignKeyViolation)),
+                StatusCode::CONFLICT,
+                "in_use",
+            ),
+            (
+                query_rejection(diesel::result::Error::RollbackTransaction),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+            ),
+            (reject::not_found(), StatusCode::NOT_FOUND, "not_found"),
+        ];
+        for (rejection, status, error) in cases {
+            let message = ErrorMessage::from_rejection(&rejection);
+            assert_eq!((message.code, message.error), (status.as_u16(), error));
+        }
+    }
+
+    #[tokio::test]
+    async fn handled_rejections_carry_the_status() {
+        let filter = warp::any()
+            .and_then(|| async {
+                Err::<String, _>(reject::custom(InputError::new(
+                    "if-match",
+                    ValidationError::Outdated,
+                )))
+            })
+            .recover(handle_rejection);
+        let response = warp::test::request().reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["code"], 412);
+        assert_eq!(body["error"], "precondition_failed");
+        assert_eq!(
+            body["message"],
+            "`if-match` does not match the current version"
+        );
+    }
 }
@@ -0,0 +1,170 @@
+use argon2::password_hash::{Ident, Output, ParamsString, PasswordHash, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::pbkdf2::{self, PBKDF2_HMAC_SHA512};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::convert::TryFrom;
+use std::num::NonZeroU32;
+
+// Argon2's hashing and verification traits, imported anonymously so they do
+// not clash with ours.
+use argon2::password_hash::{PasswordHasher as _, PasswordVerifier as _};
+
+const PBKDF2_SHA512: Ident = Ident::new_unwrap("pbkdf2-sha512");
+const SALT_LENGTH: usize = 16;
+
+// Hashes passwords into PHC strings, `$<id>$<params>$<salt>$<hash>`, which
+// carry everything needed to verify them later. Hashes made by any supported
+// scheme can be verified with `verify_password`, regardless of the hasher
+// currently configured.
+pub trait PasswordHasher: Send + Sync {
+    fn hash(&self, password: &str) -> String;
+
+    // Whether `hash` was made by another scheme, or with weaker parameters
+    // than this hasher would use.
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool;
+}
+
+// Defaults to the parameters recommended by OWASP, 19 MiB of memory, two
+// iterations and no parallelism.
+#[derive(Default)]
+pub struct Argon2idHasher {
+    params: Params,
+}
+
+impl Argon2idHasher {
+    pub fn new(
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<Argon2idHasher, argon2::Error> {
+        Ok(Argon2idHasher {
+            params: Params::new(memory_kib, iterations, parallelism, None)?,
+        })
+    }
+}
+
+impl PasswordHasher for Argon2idHasher {
+    fn hash(&self, password: &str) -> String {
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone());
+        argon2
+            .hash_password(password.as_bytes(), &random_salt())
+            .expect("argon2 parameters are validated on construction")
+            .to_string()
+    }
+
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        if hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+        {
+            return true;
+        }
+        match Params::try_from(hash) {
+            Ok(params) => {
+                params.m_cost() < self.params.m_cost()
+                    || params.t_cost() < self.params.t_cost()
+                    || params.p_cost() < self.params.p_cost()
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+// Only kept around to verify, and if configured keep producing, the hashes
+// created before Argon2id became the default.
+pub struct Pbkdf2Hasher {
+    pub iterations: NonZeroU32,
+}
+
+impl PasswordHasher for Pbkdf2Hasher {
+    fn hash(&self, password: &str) -> String {
+        let salt = random_salt();
+        let mut salt_bytes = [0u8; SALT_LENGTH];
+        let salt_bytes = salt
+            .decode_b64(&mut salt_bytes)
+            .expect("freshly encoded salts decode");
+        let mut hashed = [0u8; ring::digest::SHA512_OUTPUT_LEN];
+        pbkdf2::derive(
+            PBKDF2_HMAC_SHA512,
+            self.iterations,
+            salt_bytes,
+            password.as_bytes(),
+            &mut hashed,
+        );
+        let mut params = ParamsString::new();
+        params
+            .add_decimal("i", self.iterations.get())
+            .expect("a single parameter fits");
+        PasswordHash {
+            algorithm: PBKDF2_SHA512,
+            version: None,
+            params,
+            salt: Some(salt.as_salt()),
+            hash: Some(Output::new(&hashed).expect("SHA-512 output fits")),
+        }
+        .to_string()
+    }
+
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        match pbkdf2_iterations(hash) {
+            Some(iterations) => iterations < self.iterations,
+            None => true,
+        }
+    }
+}
+
+fn random_salt() -> SaltString {
+    let mut bytes = [0u8; SALT_LENGTH];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system random number generator is available");
+    SaltString::encode_b64(&bytes).expect("salt length is within bounds")
+}
+
+fn pbkdf2_iterations(hash: &PasswordHash) -> Option<NonZeroU32> {
+    if hash.algorithm != PBKDF2_SHA512 {
+        return None;
+    }
+    hash.params.get_decimal("i").and_then(NonZeroU32::new)
+}
+
+fn verify_pbkdf2(password: &str, hash: &PasswordHash) -> bool {
+    let (iterations, salt, expected) = match (pbkdf2_iterations(hash), hash.salt, hash.hash) {
+        (Some(iterations), Some(salt), Some(expected)) => (iterations, salt, expected),
+        _ => return false,
+    };
+    let mut salt_bytes = [0u8; 64];
+    match salt.decode_b64(&mut salt_bytes) {
+        Ok(salt_bytes) => pbkdf2::verify(
+            PBKDF2_HMAC_SHA512,
+            iterations,
+            salt_bytes,
+            password.as_bytes(),
+            expected.as_bytes(),
+        )
+        .is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Verifies against whatever scheme and parameters the hash was made with.
+pub fn verify_password(password: &str, encoded: &str) -> bool {
+    let hash = match PasswordHash::new(encoded) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+    if hash.algorithm == PBKDF2_SHA512 {
+        verify_pbkdf2(password, &hash)
+    } else {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    }
+}
+
+// Unreadable hashes are always worth replacing.
+pub fn needs_rehash(hasher: &dyn PasswordHasher, encoded: &str) -> bool {
+    match PasswordHash::new(encoded) {
+        Ok(hash) => hasher.needs_rehash(&hash),
+        Err(_) => true,
+    }
+}
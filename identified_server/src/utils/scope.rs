@@ -0,0 +1,100 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+// What an internal user is allowed to do, every route requires one of these.
+// Stored as text, so scopes written by a newer version are skipped rather
+// than refused.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    #[serde(rename = "roles:read")]
+    RolesRead,
+    #[serde(rename = "roles:write")]
+    RolesWrite,
+    #[serde(rename = "permissions:read")]
+    PermissionsRead,
+    #[serde(rename = "permissions:write")]
+    PermissionsWrite,
+    // Checking and subscribing to the decisions of permissions
+    #[serde(rename = "permissions:check")]
+    PermissionsCheck,
+    #[serde(rename = "namespaces:manage")]
+    NamespacesManage,
+    // Managing internal users, their scopes and sessions
+    #[serde(rename = "internal:manage")]
+    InternalManage,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 9] = [
+        Scope::UsersRead,
+        Scope::UsersWrite,
+        Scope::RolesRead,
+        Scope::RolesWrite,
+        Scope::PermissionsRead,
+        Scope::PermissionsWrite,
+        Scope::PermissionsCheck,
+        Scope::NamespacesManage,
+        Scope::InternalManage,
+    ];
+
+    // What internal users could do before scopes existed, everything within
+    // their namespaces.
+    pub const DEFAULT: [Scope; 7] = [
+        Scope::UsersRead,
+        Scope::UsersWrite,
+        Scope::RolesRead,
+        Scope::RolesWrite,
+        Scope::PermissionsRead,
+        Scope::PermissionsWrite,
+        Scope::PermissionsCheck,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+            Scope::RolesRead => "roles:read",
+            Scope::RolesWrite => "roles:write",
+            Scope::PermissionsRead => "permissions:read",
+            Scope::PermissionsWrite => "permissions:write",
+            Scope::PermissionsCheck => "permissions:check",
+            Scope::NamespacesManage => "namespaces:manage",
+            Scope::InternalManage => "internal:manage",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Scope, String> {
+        Scope::ALL
+            .iter()
+            .find(|scope| scope.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown scope {}", s))
+    }
+}
+
+// Sorted and without duplicates, the way they are stored.
+pub fn to_strings(scopes: &[Scope]) -> Vec<String> {
+    let mut strings: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+    strings.sort_unstable();
+    strings.dedup();
+    strings
+}
+
+pub fn from_strings(strings: &[String]) -> Vec<Scope> {
+    strings.iter().filter_map(|s| s.parse().ok()).collect()
+}
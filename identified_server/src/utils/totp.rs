@@ -0,0 +1,107 @@
+use data_encoding::BASE32_NOPAD;
+use rand::{thread_rng, RngCore};
+use ring::hmac;
+
+// RFC 6238 with the parameters every authenticator app understands: SHA-1,
+// 6 digits and 30 second steps.
+pub const DIGITS: u32 = 6;
+pub const STEP_SECONDS: i64 = 30;
+// Codes from the step before and after are accepted too, to allow for clocks
+// that are slightly off.
+const SKEW_STEPS: i64 = 1;
+
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+pub fn step(unix_seconds: i64) -> i64 {
+    unix_seconds.div_euclid(STEP_SECONDS)
+}
+
+// RFC 4226 HOTP for the counter `step`.
+pub fn code_at(secret: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let truncated = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    truncated % 10u32.pow(DIGITS)
+}
+
+// Returns the step the code belongs to. Steps up to and including
+// `last_step` are refused, so a code cannot be used twice.
+pub fn verify(secret: &str, code: &str, unix_seconds: i64, last_step: i64) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = step(unix_seconds);
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|&candidate| candidate > last_step)
+        .find(|&candidate| code_at(&secret, candidate) == code)
+}
+
+// The `otpauth://` URI authenticator apps import, usually through a QR code.
+pub fn provisioning_uri(secret: &str, issuer: &str, account: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+        issuer = percent_encode(issuer),
+        account = percent_encode(account),
+        secret = secret,
+        digits = DIGITS,
+        period = STEP_SECONDS,
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From appendix B of RFC 6238, truncated to six digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn codes_match_rfc_6238() {
+        assert_eq!(code_at(RFC_SECRET, step(59)), 287_082);
+        assert_eq!(code_at(RFC_SECRET, step(1_111_111_109)), 81_804);
+        assert_eq!(code_at(RFC_SECRET, step(2_000_000_000)), 279_037);
+    }
+
+    #[test]
+    fn codes_are_accepted_once_within_the_window() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let now = 1_111_111_109;
+        assert_eq!(verify(&secret, "081804", now, 0), Some(step(now)));
+        assert_eq!(verify(&secret, "081804", now + 30, 0), Some(step(now)));
+        assert_eq!(verify(&secret, "081804", now + 90, 0), None);
+        assert_eq!(verify(&secret, "081804", now, step(now)), None);
+        assert_eq!(verify(&secret, "81804", now, 0), None);
+    }
+
+    #[test]
+    fn provisioning_uri_is_escaped() {
+        let uri = provisioning_uri("ABC", "identified", "root user@admin.com");
+        assert!(uri.starts_with("otpauth://totp/identified:root%20user@admin.com?secret=ABC&"));
+    }
+}